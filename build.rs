@@ -0,0 +1,21 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bake the commit and build time into the binary for `GET /version`.
+fn main() {
+    let git_sha = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    let build_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    // seconds since the epoch; formatted at runtime, where chrono is available
+    println!("cargo:rustc-env=BUILD_TIME={}", build_time);
+}
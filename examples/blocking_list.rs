@@ -0,0 +1,116 @@
+// Runs a few hundred concurrent list requests over 10k records on an in-memory store, once
+// with the structsy scan called straight from the async task and once through
+// `spawn_blocking`, like the handlers before and after `run_blocking`:
+//
+//     cargo run --release --example blocking_list
+//
+// Next to the lists a probe asks for a 1ms timer over and over, the way an unrelated cheap
+// request would.  How late it wakes is how long the lists keep every worker to themselves.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use structsy::derive::Persistent;
+use structsy::{Structsy, StructsyTx};
+use tokio::runtime::Runtime;
+
+const ROWS: u32 = 10_000;
+const REQUESTS: usize = 300;
+const WORKERS: usize = 4;
+const PAGE: usize = 20;
+const TICK: Duration = Duration::from_millis(1);
+
+#[derive(Persistent)]
+struct Drink {
+    brand: String,
+    size: u32,
+}
+
+fn fill(db: &Structsy) -> structsy::SRes<()> {
+    let mut tx = db.begin()?;
+    for n in 0..ROWS {
+        tx.insert(&Drink {
+            brand: format!("brand-{}", n % 40),
+            size: n % 1000 + 1,
+        })?;
+    }
+    tx.commit()
+}
+
+// What a list request does with the store: count the matches and keep the first page.
+fn list(db: &Structsy) -> usize {
+    let mut page = Vec::with_capacity(PAGE);
+    let mut total = 0;
+    for (_, drink) in db.scan::<Drink>().expect("the scan starts") {
+        if drink.size > 100 {
+            if page.len() < PAGE {
+                page.push(drink.brand);
+            }
+            total += 1;
+        }
+    }
+    total
+}
+
+// Wall time for every list, and the worst delay of the probe while they ran.
+fn run(runtime: &Runtime, db: &Structsy, blocking: bool) -> (Duration, Duration) {
+    runtime.block_on(async {
+        let done = Arc::new(AtomicBool::new(false));
+        let probe = tokio::spawn({
+            let done = done.clone();
+            async move {
+                let mut worst = Duration::ZERO;
+                while !done.load(Ordering::Acquire) {
+                    let start = Instant::now();
+                    tokio::time::sleep(TICK).await;
+                    worst = worst.max(start.elapsed().saturating_sub(TICK));
+                }
+                worst
+            }
+        });
+        let start = Instant::now();
+        let requests: Vec<_> = (0..REQUESTS)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    if blocking {
+                        tokio::task::spawn_blocking(move || list(&db))
+                            .await
+                            .expect("the list runs")
+                    } else {
+                        list(&db)
+                    }
+                })
+            })
+            .collect();
+        for request in requests {
+            request.await.expect("the request runs");
+        }
+        let elapsed = start.elapsed();
+        done.store(true, Ordering::Release);
+        (elapsed, probe.await.expect("the probe runs"))
+    })
+}
+
+fn main() -> structsy::SRes<()> {
+    let db = Structsy::memory()?;
+    db.define::<Drink>()?;
+    fill(&db)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(WORKERS)
+        .enable_time()
+        .build()
+        .expect("the runtime starts");
+    println!(
+        "{} rows, {} concurrent lists, {} async workers",
+        ROWS, REQUESTS, WORKERS
+    );
+    for (name, blocking) in [("inline", false), ("blocking", true)] {
+        let (elapsed, worst) = run(&runtime, &db, blocking);
+        println!(
+            "{:<8} {:>10.3?} for every list  {:>10.3?} worst probe delay",
+            name, elapsed, worst
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+// Times a `?brand=` list filter over 10k records, through a cluster index on the normalised
+// brand and through a full scan, on an in-memory store:
+//
+//     cargo run --release --example brand_lookup
+//
+// The two types differ only in the index, like `Coffee` before and after `brand_key`.
+use std::time::{Duration, Instant};
+
+use structsy::derive::{queries, Persistent};
+use structsy::{Structsy, StructsyTx};
+
+const ROWS: u32 = 10_000;
+const RUNS: u32 = 50;
+// One brand of the 40, so this matches 2.5% of the rows.
+const BRAND: &str = "Brand-7";
+
+#[derive(Persistent)]
+struct Indexed {
+    brand: String,
+    #[index(mode = "cluster")]
+    brand_key: String,
+    size: u32,
+}
+
+#[queries(Indexed)]
+trait IndexedQuery {
+    fn by_brand_key(self, brand_key: String) -> Self;
+}
+
+#[derive(Persistent)]
+struct Scanned {
+    brand: String,
+    size: u32,
+}
+
+// The same normalisation as `beverage::brand_key`.
+fn brand_key(brand: &str) -> String {
+    brand.trim().to_lowercase()
+}
+
+fn fill(db: &Structsy) -> structsy::SRes<()> {
+    let mut tx = db.begin()?;
+    for n in 0..ROWS {
+        let (brand, size) = (format!("Brand-{}", n % 40), n % 1000 + 1);
+        tx.insert(&Indexed {
+            brand_key: brand_key(&brand),
+            brand: brand.clone(),
+            size,
+        })?;
+        tx.insert(&Scanned { brand, size })?;
+    }
+    tx.commit()
+}
+
+// Average time of one run of `query`, which returns how many records it found.
+fn time(name: &str, mut query: impl FnMut() -> usize) {
+    let expected = query();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        assert_eq!(query(), expected);
+    }
+    let each: Duration = start.elapsed() / RUNS;
+    println!(
+        "{:<8} {:>4} matches  {:>10.3?} per query",
+        name, expected, each
+    );
+}
+
+fn main() -> structsy::SRes<()> {
+    let db = Structsy::memory()?;
+    db.define::<Indexed>()?;
+    db.define::<Scanned>()?;
+    fill(&db)?;
+    println!("{} rows, brand {:?}, {} runs each", ROWS, BRAND, RUNS);
+    let key = brand_key(BRAND);
+    time("index", || {
+        db.query::<Indexed>()
+            .by_brand_key(key.clone())
+            .fetch()
+            .count()
+    });
+    time("scan", || {
+        db.scan::<Scanned>()
+            .expect("the scan starts")
+            .filter(|(_, value)| brand_key(&value.brand) == key)
+            .count()
+    });
+    Ok(())
+}
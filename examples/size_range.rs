@@ -0,0 +1,79 @@
+// Times a `?min_size=&max_size=` style query over 10k records, through a range index on `size`
+// and through a full scan, on an in-memory store:
+//
+//     cargo run --release --example size_range
+//
+// The two types differ only in the index, like `Coffee` before and after it got one.
+use std::ops::{RangeBounds, RangeInclusive};
+use std::time::{Duration, Instant};
+
+use structsy::derive::{queries, Persistent};
+use structsy::{Structsy, StructsyTx};
+
+const ROWS: u32 = 10_000;
+const RUNS: u32 = 50;
+// Sizes are spread over 1..=1000, so this matches about 5% of the rows.
+const SIZES: RangeInclusive<u32> = 500..=549;
+
+#[derive(Persistent)]
+struct Indexed {
+    brand: String,
+    #[index(mode = "cluster")]
+    size: u32,
+}
+
+#[queries(Indexed)]
+trait IndexedQuery {
+    fn by_size<R: RangeBounds<u32>>(self, size: R) -> Self;
+}
+
+#[derive(Persistent)]
+struct Scanned {
+    brand: String,
+    size: u32,
+}
+
+fn fill(db: &Structsy) -> structsy::SRes<()> {
+    let mut tx = db.begin()?;
+    for n in 0..ROWS {
+        let (brand, size) = (format!("brand-{}", n % 40), n % 1000 + 1);
+        tx.insert(&Indexed {
+            brand: brand.clone(),
+            size,
+        })?;
+        tx.insert(&Scanned { brand, size })?;
+    }
+    tx.commit()
+}
+
+// Average time of one run of `query`, which returns how many records it found.
+fn time(name: &str, mut query: impl FnMut() -> usize) {
+    let expected = query();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        assert_eq!(query(), expected);
+    }
+    let each: Duration = start.elapsed() / RUNS;
+    println!(
+        "{:<8} {:>4} matches  {:>10.3?} per query",
+        name, expected, each
+    );
+}
+
+fn main() -> structsy::SRes<()> {
+    let db = Structsy::memory()?;
+    db.define::<Indexed>()?;
+    db.define::<Scanned>()?;
+    fill(&db)?;
+    println!("{} rows, size in {:?}, {} runs each", ROWS, SIZES, RUNS);
+    time("index", || {
+        db.query::<Indexed>().by_size(SIZES).fetch().count()
+    });
+    time("scan", || {
+        db.scan::<Scanned>()
+            .expect("the scan starts")
+            .filter(|(_, value)| SIZES.contains(&value.size))
+            .count()
+    });
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+
+use axum::{extract::State, middleware::from_fn_with_state, routing::post, Router};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::compact;
+use crate::idempotency;
+use crate::problem::Problem;
+use crate::{
+    auth, run_blocking, with_tx, with_writes_paused, AppError, AppJson, AppQuery, AppState,
+    MEMORY_DB,
+};
+
+#[derive(Serialize, ToSchema)]
+struct Backup {
+    // where the copy of DB_PATH was written, as given by BACKUP_DIR
+    path: String,
+    // every copy written, that of DB_PATH first; with DB_FILES=per-kind one per file
+    paths: Vec<String>,
+}
+
+// Copy each database file to `<BACKUP_DIR>/<name>-<UTC time>.<ext>`.  Writes are held back
+// while copying, so no copy catches a transaction half-committed and per-kind copies are of
+// the same moment.
+#[utoipa::path(
+    post,
+    path = "/admin/backup",
+    tag = "admin",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The backup was written", body = Backup),
+        (
+            status = 400,
+            description = "The database is in memory, there is no file to copy",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn backup(State(state): State<AppState>) -> Result<AppJson<Backup>, AppError> {
+    let db_path = state.config.db_path.clone();
+    if db_path == MEMORY_DB {
+        return Err(AppError::BadRequest(
+            "the database is in memory, there is no file to back up".to_owned(),
+        ));
+    }
+    let dir = PathBuf::from(&state.config.backup_dir);
+    run_blocking(move || {
+        std::fs::create_dir_all(&dir)?;
+        let copies: Vec<_> = state
+            .db
+            .files()
+            .iter()
+            .map(|file| (&file.path, dir.join(backup_name(Path::new(&file.path)))))
+            .collect();
+        with_writes_paused(|| {
+            copies
+                .iter()
+                .try_for_each(|(source, target)| std::fs::copy(source, target).map(drop))
+        })?;
+        let mut paths = Vec::new();
+        for (source, target) in copies {
+            let path = target.display().to_string();
+            tracing::info!("backed up {} to {}", source, path);
+            paths.push(path);
+        }
+        Ok(AppJson(Backup {
+            path: paths[0].clone(),
+            paths,
+        }))
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct Compacted {
+    // expired `Idempotency-Key` records deleted
+    idempotency_keys: usize,
+    // whether every record was copied into a fresh file, as asked with `?rebuild=true`
+    rebuilt: bool,
+    // size of the database files, together, before and after
+    before_bytes: u64,
+    after_bytes: u64,
+}
+
+// `?rebuild=true` for `POST /admin/compact`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CompactParams {
+    #[serde(default)]
+    rebuild: bool,
+}
+
+// Reclaim what can be reclaimed without downtime.  First the expired idempotency keys are
+// deleted, one write transaction per database file; persy reuses the freed pages for later
+// writes, but gives back only those at the end of a file.  With `?rebuild=true` every file
+// is then copied into a fresh one, see `compact`, with writes held back until the copies are
+// in place.  The copies give every record a new id, so clients have to read their ids again.
+#[utoipa::path(
+    post,
+    path = "/admin/compact",
+    tag = "admin",
+    params(CompactParams),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "What was reclaimed", body = Compacted),
+        (
+            status = 400,
+            description = "The database is in memory, there is no file to compact",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn compact(
+    State(state): State<AppState>,
+    AppQuery(params): AppQuery<CompactParams>,
+) -> Result<AppJson<Compacted>, AppError> {
+    let db_path = state.config.db_path.clone();
+    if db_path == MEMORY_DB {
+        return Err(AppError::BadRequest(
+            "the database is in memory, there is no file to compact".to_owned(),
+        ));
+    }
+    run_blocking(move || {
+        let files = state.db.files();
+        let size = || -> std::io::Result<u64> {
+            files
+                .iter()
+                .map(|file| Ok(std::fs::metadata(&file.path)?.len()))
+                .sum()
+        };
+        let before_bytes = size()?;
+        let ttl = state.config.idempotency_ttl;
+        let mut idempotency_keys = 0;
+        for file in files {
+            idempotency_keys += with_tx(file, |tx| Ok(idempotency::purge_expired(tx, ttl)?))?;
+        }
+        if params.rebuild {
+            with_writes_paused(|| compact::rebuild(&state.db))?;
+        }
+        let after_bytes = size()?;
+        tracing::info!(
+            "compacted {}: {} expired idempotency keys, {}rebuilt, {} -> {} bytes",
+            db_path,
+            idempotency_keys,
+            if params.rebuild { "" } else { "not " },
+            before_bytes,
+            after_bytes
+        );
+        Ok(AppJson(Compacted {
+            idempotency_keys,
+            rebuilt: params.rebuild,
+            before_bytes,
+            after_bytes,
+        }))
+    })
+    .await
+}
+
+// The UTC time in a backup's file name.
+pub const BACKUP_TIME: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+// `track.db` becomes `track-20240102T030405.678Z.db`; the milliseconds keep two backups
+// taken in the same second apart.
+fn backup_name(db_path: &Path) -> String {
+    let stem = db_path
+        .file_stem()
+        .map_or("db".into(), |stem| stem.to_string_lossy());
+    let time = chrono::Utc::now().format(BACKUP_TIME);
+    match db_path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, time, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, time),
+    }
+}
+
+// Outside the maintenance guard: taking a backup is a likely reason for switching it on.
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/admin/backup", post(backup))
+        .route("/admin/compact", post(compact))
+        .route_layer(from_fn_with_state(state.clone(), auth::require_api_key))
+        .with_state(state)
+}
@@ -0,0 +1,135 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::request::Parts,
+    middleware::from_fn_with_state,
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use structsy::{derive::Persistent, OwnedSytx, SRes, StructsyTx};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::compact::{self, NewId, Remap};
+use crate::problem::Problem;
+use crate::timestamp::Timestamp;
+use crate::{auth, run_blocking, AppError, AppJson, AppQuery, AppState};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 1000;
+
+// One write to a drink record, stored in the same transaction as the write itself.
+#[derive(Serialize, Persistent, ToSchema)]
+pub struct AuditEntry {
+    // create, update, delete, restore, purchase, reserve, release, restock, rename,
+    // reprice or import
+    op: String,
+    kind: String,
+    ref_id: String,
+    // `x-request-id` of the request that made the change, to find it in the traces
+    request_id: Option<String>,
+    timestamp: Timestamp,
+}
+
+impl Remap for AuditEntry {
+    fn remap(&mut self, new_id: NewId) {
+        compact::remap(&self.kind, &mut self.ref_id, new_id);
+    }
+}
+
+// The id `SetRequestIdLayer` gave the current request, if any.  Changes made outside a request,
+// from the command line, use the empty default.
+#[derive(Default)]
+pub struct RequestId(Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        let id = parts
+            .extensions
+            .get::<tower_http::request_id::RequestId>()
+            .and_then(|id| id.header_value().to_str().ok())
+            .map(str::to_owned);
+        Ok(RequestId(id))
+    }
+}
+
+// Add an entry for `op` on `kind` `ref_id` to the transaction making the change.
+pub fn record(
+    tx: &mut OwnedSytx,
+    op: &str,
+    kind: &str,
+    ref_id: &str,
+    request_id: &RequestId,
+) -> SRes<()> {
+    tx.insert(&AuditEntry {
+        op: op.to_owned(),
+        kind: kind.to_owned(),
+        ref_id: ref_id.to_owned(),
+        request_id: request_id.0.clone(),
+        timestamp: Timestamp::now(),
+    })?;
+    Ok(())
+}
+
+// `?limit=`, defaulting to `DEFAULT_LIMIT` and clamped to `MAX_LIMIT`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditParams {
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Entries {
+    entries: Vec<AuditEntry>,
+}
+
+// The most recent entries, newest first.
+#[utoipa::path(
+    get,
+    path = "/audit",
+    tag = "audit",
+    params(AuditParams),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The latest entries", body = Entries),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn recent(
+    State(state): State<AppState>,
+    AppQuery(params): AppQuery<AuditParams>,
+) -> Result<AppJson<Entries>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    run_blocking(move || {
+        let mut entries = Vec::new();
+        for file in state.db.files() {
+            entries.extend(
+                file.connection()
+                    .scan::<AuditEntry>()?
+                    .map(|(_, entry)| entry),
+            );
+        }
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+        entries.truncate(limit);
+        Ok(AppJson(Entries { entries }))
+    })
+    .await
+}
+
+// The log says who changed what, so reading it needs the API key.
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/audit", get(recent))
+        .route_layer(from_fn_with_state(state.clone(), auth::require_api_key))
+        .with_state(state)
+}
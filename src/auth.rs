@@ -0,0 +1,40 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{AppError, AppState};
+
+// Route layer for the mutating routes: the request must carry `X-API-Key` matching the
+// API_KEY setting.  With no key configured every request is refused, unless AUTH_DISABLED
+// lets them all through.
+pub async fn require_api_key(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(expected) = &state.config.api_key else {
+        if state.config.auth_disabled {
+            return Ok(next.run(req).await);
+        }
+        return Err(AppError::Unauthorized(
+            "no API key is configured, the route is closed".to_owned(),
+        ));
+    };
+    let given = req
+        .headers()
+        .get("x-api-key")
+        .map(|key| key.as_bytes())
+        .ok_or_else(|| AppError::Unauthorized("missing X-API-Key header".to_owned()))?;
+    if !constant_time_eq(given, expected.as_bytes()) {
+        return Err(AppError::Unauthorized("invalid API key".to_owned()));
+    }
+    Ok(next.run(req).await)
+}
+
+// Compare without bailing out at the first difference, so response times don't leak how much
+// of a guessed key was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
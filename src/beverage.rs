@@ -0,0 +1,2924 @@
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::from_fn_with_state,
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post},
+    Router,
+};
+use rand::seq::IteratorRandom;
+use serde::{
+    de::{self, DeserializeOwned, IgnoredAny, MapAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    ops::{RangeBounds, RangeInclusive},
+};
+use structsy::{
+    derive::{queries, Persistent},
+    OwnedSytx, Persistent, Ref, Structsy, StructsyIter, StructsyTx,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use utoipa::openapi::{
+    path::{HttpMethod, OperationBuilder, Paths},
+    schema::{AllOfBuilder, Components, ObjectBuilder, OneOfBuilder, Schema, Type},
+    OpenApiBuilder, RefOr,
+};
+use utoipa::{IntoParams, PartialSchema, ToSchema};
+
+use crate::audit::{self, RequestId};
+use crate::config::Config;
+use crate::db::DbFile;
+use crate::idempotency::{self, IdempotencyRecord};
+use crate::openapi::{
+    admin, also, array, empty, header_param, id, json, json_body, ok, operation, path_param,
+    problem, query, register, schema, string,
+};
+use crate::reservation::{self, Reservation, Reserved};
+use crate::sale::Sale;
+use crate::size::SizeCategory;
+use crate::timestamp::Timestamp;
+use crate::webhook::SaleEvent;
+use crate::{auth, metrics};
+use crate::{
+    parse_ref, run_blocking, with_tx, with_tx_or_dry_run, AppError, AppJson, AppQuery, AppState,
+    API_PREFIX,
+};
+
+// Version given to a record when it's created; 0 is what a body without `version` reads as.
+pub const FIRST_VERSION: u64 = 1;
+
+// Everything the generic handlers need to know about a drink type.  Adding a new drink is a
+// matter of declaring the struct, implementing this trait and nesting `routes::<T>()`.
+pub trait Beverage: Persistent + Serialize + DeserializeOwned + ToSchema + Send + 'static {
+    // Singular name, used in error messages and as the JSON key of a single item
+    const KIND: &'static str;
+    // Plural name, used as the JSON key of a list
+    const PLURAL: &'static str;
+    // Field names on the wire, for `?fields=`
+    const FIELDS: &'static [&'static str];
+
+    fn brand(&self) -> &str;
+    // Callers refresh `brand_key` afterwards.
+    fn set_brand(&mut self, brand: String);
+    fn machine_id(&self) -> &str;
+    fn size(&self) -> u32;
+    // `None` only until `refresh_derived`, for a body that left it out.
+    fn category(&self) -> Option<SizeCategory>;
+    fn time(&self) -> Timestamp;
+    fn set_time(&mut self, time: Timestamp);
+    fn stock(&self) -> u32;
+    fn set_stock(&mut self, stock: u32);
+    fn price_cents(&self) -> u32;
+    fn set_price_cents(&mut self, price_cents: u32);
+    fn version(&self) -> u64;
+    // Every write bumps the version, so this also stamps `updated_at`.
+    fn set_version(&mut self, version: u64);
+    fn updated_at(&self) -> Timestamp;
+    fn purchases(&self) -> u64;
+    fn set_purchases(&mut self, purchases: u64);
+    fn deleted_at(&self) -> Option<Timestamp>;
+    fn set_deleted_at(&mut self, deleted_at: Option<Timestamp>);
+
+    fn is_deleted(&self) -> bool {
+        self.deleted_at().is_some()
+    }
+
+    // Body of a create: only what a client may set, so the stored layout can change without
+    // changing what clients send.
+    type Create: DeserializeOwned + ToSchema + Send + 'static;
+
+    // The record `create` describes, created at `time`, with every server-managed field
+    // stamped as for a first write.
+    fn from_create(create: Self::Create, time: Timestamp) -> Self;
+
+    // Body of a partial update; every field is optional.
+    type Patch: DeserializeOwned + ToSchema + Clone + Send + 'static;
+
+    // Overwrite the fields present in `patch`, leaving the rest as stored.
+    fn apply(&mut self, patch: Self::Patch);
+
+    // Recompute the fields derived from others, the indexed `brand_key` from `brand` and
+    // `category` from `size`; call before every insert or update.
+    fn refresh_derived(&mut self);
+
+    // Records whose brand matches `brand` (see `brand_key`), looked up through the index.
+    fn find_by_brand(db: &Structsy, brand: &str) -> StructsyIter<'static, (Ref<Self>, Self)>;
+
+    // Records stocked in machine `machine_id`, looked up through the index.
+    fn find_by_machine(db: &Structsy, machine_id: &str)
+        -> StructsyIter<'static, (Ref<Self>, Self)>;
+
+    // Records with a size in `sizes`, looked up through the index.
+    fn find_by_size(
+        db: &Structsy,
+        sizes: RangeInclusive<u32>,
+    ) -> StructsyIter<'static, (Ref<Self>, Self)>;
+
+    // The same lookup inside a transaction, seeing what it has written so far.
+    fn find_by_brand_tx<'a>(
+        tx: &'a mut OwnedSytx,
+        brand: &str,
+    ) -> StructsyIter<'a, (Ref<Self>, Self)>;
+
+    // Reject records that deserialize fine but make no sense, listing every problem found.
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        if self.brand().trim().is_empty() {
+            problems.push("brand must not be empty".to_owned());
+        }
+        if self.machine_id().trim().is_empty() {
+            problems.push("machine_id must not be empty".to_owned());
+        }
+        if self.size() == 0 {
+            problems.push("size must be greater than 0".to_owned());
+        }
+        let fits = SizeCategory::of(self.size());
+        if let Some(category) = self.category().filter(|category| *category != fits) {
+            problems.push(format!(
+                "category {} does not match size {}, which is {}",
+                category,
+                self.size(),
+                fits
+            ));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+// Every drink type carries the same bookkeeping fields, so the trait impls are generated.
+// Fields specific to one type are listed after the names so its patch can set them too.
+macro_rules! impl_beverage {
+    ($ty:ident, $query:ident, $create:ident, $patch:ident, $kind:literal, $plural:literal $(, $field:ident: $field_ty:ty)*) => {
+        #[queries($ty)]
+        trait $query {
+            fn by_brand_key(self, brand_key: String) -> Self;
+            fn by_machine_id(self, machine_id: String) -> Self;
+            fn by_size<R: RangeBounds<u32>>(self, size: R) -> Self;
+        }
+
+        // The server-managed fields (`time`, `version`, `updated_at`, `purchases` and
+        // `deleted_at`) are rejected rather than ignored
+        #[derive(Deserialize, ToSchema)]
+        #[serde(deny_unknown_fields)]
+        pub struct $create {
+            brand: String,
+            machine_id: String,
+            size: u32,
+            // must match `size`; left out, it is derived from it
+            #[serde(default)]
+            category: Option<SizeCategory>,
+            #[serde(default)]
+            stock: u32,
+            #[serde(default)]
+            price_cents: u32,
+            $($field: $field_ty,)*
+        }
+
+        // `time` is server managed and so not patchable
+        #[derive(Deserialize, Clone, ToSchema)]
+        #[serde(deny_unknown_fields)]
+        pub struct $patch {
+            brand: Option<String>,
+            machine_id: Option<String>,
+            size: Option<u32>,
+            // must match `size`; left out, it follows a new `size`
+            category: Option<SizeCategory>,
+            stock: Option<u32>,
+            price_cents: Option<u32>,
+            $($field: Option<$field_ty>,)*
+        }
+
+        impl Beverage for $ty {
+            const KIND: &'static str = $kind;
+            const PLURAL: &'static str = $plural;
+            const FIELDS: &'static [&'static str] = &[
+                "brand", "machine_id", "size", "category", "time", "stock", "price_cents", "version", "updated_at", "purchases", "deleted_at"
+                $(, stringify!($field))*
+            ];
+
+            type Create = $create;
+
+            fn from_create(create: $create, time: Timestamp) -> Self {
+                $ty {
+                    brand_key: brand_key(&create.brand),
+                    brand: create.brand,
+                    machine_id: create.machine_id,
+                    size: create.size,
+                    category: create.category,
+                    time,
+                    stock: create.stock,
+                    price_cents: create.price_cents,
+                    version: FIRST_VERSION,
+                    updated_at: time,
+                    purchases: 0,
+                    deleted_at: None,
+                    $($field: create.$field,)*
+                }
+            }
+
+            type Patch = $patch;
+
+            fn apply(&mut self, patch: $patch) {
+                if let Some(brand) = patch.brand {
+                    self.brand = brand;
+                }
+                if let Some(machine_id) = patch.machine_id {
+                    self.machine_id = machine_id;
+                }
+                if let Some(size) = patch.size {
+                    self.size = size;
+                    self.category = patch.category;
+                } else if patch.category.is_some() {
+                    self.category = patch.category;
+                }
+                if let Some(stock) = patch.stock {
+                    self.stock = stock;
+                }
+                if let Some(price_cents) = patch.price_cents {
+                    self.price_cents = price_cents;
+                }
+                $(if let Some($field) = patch.$field {
+                    self.$field = $field;
+                })*
+            }
+
+            fn brand(&self) -> &str {
+                &self.brand
+            }
+
+            fn set_brand(&mut self, brand: String) {
+                self.brand = brand;
+            }
+
+            fn machine_id(&self) -> &str {
+                &self.machine_id
+            }
+
+            fn size(&self) -> u32 {
+                self.size
+            }
+
+            fn category(&self) -> Option<SizeCategory> {
+                self.category
+            }
+
+            fn time(&self) -> Timestamp {
+                self.time
+            }
+
+            fn set_time(&mut self, time: Timestamp) {
+                self.time = time;
+            }
+
+            fn stock(&self) -> u32 {
+                self.stock
+            }
+
+            fn set_stock(&mut self, stock: u32) {
+                self.stock = stock;
+            }
+
+            fn price_cents(&self) -> u32 {
+                self.price_cents
+            }
+
+            fn set_price_cents(&mut self, price_cents: u32) {
+                self.price_cents = price_cents;
+            }
+
+            fn version(&self) -> u64 {
+                self.version
+            }
+
+            fn set_version(&mut self, version: u64) {
+                self.version = version;
+                self.updated_at = Timestamp::now();
+            }
+
+            fn updated_at(&self) -> Timestamp {
+                self.updated_at
+            }
+
+            fn purchases(&self) -> u64 {
+                self.purchases
+            }
+
+            fn set_purchases(&mut self, purchases: u64) {
+                self.purchases = purchases;
+            }
+
+            fn deleted_at(&self) -> Option<Timestamp> {
+                self.deleted_at
+            }
+
+            fn set_deleted_at(&mut self, deleted_at: Option<Timestamp>) {
+                self.deleted_at = deleted_at;
+            }
+
+            fn refresh_derived(&mut self) {
+                self.brand_key = brand_key(&self.brand);
+                self.category = Some(SizeCategory::of(self.size));
+            }
+
+            fn find_by_brand(
+                db: &Structsy,
+                brand: &str,
+            ) -> StructsyIter<'static, (Ref<Self>, Self)> {
+                db.query::<Self>().by_brand_key(brand_key(brand)).fetch()
+            }
+
+            fn find_by_brand_tx<'a>(
+                tx: &'a mut OwnedSytx,
+                brand: &str,
+            ) -> StructsyIter<'a, (Ref<Self>, Self)> {
+                tx.query::<Self>().by_brand_key(brand_key(brand)).fetch()
+            }
+
+            fn find_by_machine(
+                db: &Structsy,
+                machine_id: &str,
+            ) -> StructsyIter<'static, (Ref<Self>, Self)> {
+                db.query::<Self>().by_machine_id(machine_id.to_owned()).fetch()
+            }
+
+            fn find_by_size(
+                db: &Structsy,
+                sizes: RangeInclusive<u32>,
+            ) -> StructsyIter<'static, (Ref<Self>, Self)> {
+                db.query::<Self>().by_size(sizes).fetch()
+            }
+        }
+    };
+}
+
+// Brands are matched case-insensitively and without surrounding whitespace; this is the form
+// stored in the indexed `brand_key` field.
+pub fn brand_key(brand: &str) -> String {
+    brand.trim().to_lowercase()
+}
+
+#[derive(Serialize, Deserialize, Persistent, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Coffee {
+    brand: String,
+    // Normalised copy of `brand`, kept for the index and never exposed on the wire
+    #[serde(skip)]
+    #[index(mode = "cluster")]
+    brand_key: String,
+    // The vending machine holding the drink; required, and matched exactly by the filter
+    #[index(mode = "cluster")]
+    machine_id: String,
+    #[index(mode = "cluster")]
+    size: u32,
+    // Small, medium or large; derived from `size`, and rejected if sent not matching it
+    #[serde(default)]
+    category: Option<SizeCategory>,
+    // Server managed: stamped on insert, preserved on update
+    #[serde(default = "Timestamp::now")]
+    time: Timestamp,
+    // Units left in the machine
+    #[serde(default)]
+    stock: u32,
+    #[serde(default)]
+    price_cents: u32,
+    // Bumped on every write; updates must send the version they read
+    #[serde(default)]
+    version: u64,
+    // Server managed: stamped by every write, along with the version bump
+    #[serde(default = "Timestamp::now")]
+    updated_at: Timestamp,
+    // Units sold, server managed like `time`
+    #[serde(default)]
+    purchases: u64,
+    // Set by a delete, which only hides the record; cleared again by a restore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<Timestamp>,
+}
+
+impl_beverage!(
+    Coffee,
+    CoffeeQuery,
+    CreateCoffee,
+    CoffeePatch,
+    "coffee",
+    "coffees"
+);
+
+#[derive(Serialize, Deserialize, Persistent, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Beer {
+    brand: String,
+    #[serde(skip)]
+    #[index(mode = "cluster")]
+    brand_key: String,
+    #[index(mode = "cluster")]
+    machine_id: String,
+    #[index(mode = "cluster")]
+    size: u32,
+    #[serde(default)]
+    category: Option<SizeCategory>,
+    // Server managed: stamped on insert, preserved on update
+    #[serde(default = "Timestamp::now")]
+    time: Timestamp,
+    // Units left in the machine
+    #[serde(default)]
+    stock: u32,
+    #[serde(default)]
+    price_cents: u32,
+    #[serde(default)]
+    version: u64,
+    #[serde(default = "Timestamp::now")]
+    updated_at: Timestamp,
+    #[serde(default)]
+    purchases: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<Timestamp>,
+}
+
+impl_beverage!(Beer, BeerQuery, CreateBeer, BeerPatch, "beer", "beers");
+
+#[derive(Serialize, Deserialize, Persistent, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Soda {
+    brand: String,
+    #[serde(skip)]
+    #[index(mode = "cluster")]
+    brand_key: String,
+    #[index(mode = "cluster")]
+    machine_id: String,
+    #[index(mode = "cluster")]
+    size: u32,
+    #[serde(default)]
+    category: Option<SizeCategory>,
+    // Server managed: stamped on insert, preserved on update
+    #[serde(default = "Timestamp::now")]
+    time: Timestamp,
+    // Units left in the machine
+    #[serde(default)]
+    stock: u32,
+    #[serde(default)]
+    price_cents: u32,
+    #[serde(default)]
+    version: u64,
+    #[serde(default = "Timestamp::now")]
+    updated_at: Timestamp,
+    #[serde(default)]
+    purchases: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<Timestamp>,
+    carbonated: bool,
+}
+
+impl_beverage!(Soda, SodaQuery, CreateSoda, SodaPatch, "soda", "sodas", carbonated: bool);
+
+// A record together with its id.  Serialized as `{"id": ..., "<kind>": {...}}` so the wire
+// format stays the same as the old per-type `CoffeeItem`/`BeerItem` structs.
+pub struct Item<T> {
+    pub id: String,
+    pub value: T,
+}
+
+impl<T: Beverage> Serialize for Item<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut item = serializer.serialize_struct("Item", 2)?;
+        item.serialize_field("id", &self.id)?;
+        item.serialize_field(T::KIND, &self.value)?;
+        item.end()
+    }
+}
+
+impl<T: Beverage> PartialSchema for Item<T> {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .property("id", string())
+            .property(T::KIND, schema::<T>())
+            .required("id")
+            .required(T::KIND)
+            .into()
+    }
+}
+
+impl<T: Beverage> ToSchema for Item<T> {
+    fn name() -> std::borrow::Cow<'static, str> {
+        format!("{}Item", T::name()).into()
+    }
+
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        schemas.push((T::name().into_owned(), T::schema()));
+        T::schemas(schemas);
+    }
+}
+
+// An item of any drink type, for the combined `/drinks` list.  Serialized like `Item` plus a
+// `"kind"` discriminator: `{"id": ..., "kind": "soda", "soda": {...}}`.
+pub enum Drink {
+    Coffee(Item<Coffee>),
+    Beer(Item<Beer>),
+    Soda(Item<Soda>),
+}
+
+impl Serialize for Drink {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        fn with_kind<T: Beverage, S: Serializer>(
+            item: &Item<T>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            let mut drink = serializer.serialize_struct("Drink", 3)?;
+            drink.serialize_field("id", &item.id)?;
+            drink.serialize_field("kind", T::KIND)?;
+            drink.serialize_field(T::KIND, &item.value)?;
+            drink.end()
+        }
+
+        match self {
+            Drink::Coffee(item) => with_kind(item, serializer),
+            Drink::Beer(item) => with_kind(item, serializer),
+            Drink::Soda(item) => with_kind(item, serializer),
+        }
+    }
+}
+
+impl PartialSchema for Drink {
+    fn schema() -> RefOr<Schema> {
+        fn with_kind<T: Beverage>() -> Schema {
+            AllOfBuilder::new()
+                .item(schema::<Item<T>>())
+                .item(
+                    ObjectBuilder::new()
+                        .property(
+                            "kind",
+                            ObjectBuilder::new()
+                                .schema_type(Type::String)
+                                .enum_values(Some([T::KIND])),
+                        )
+                        .required("kind"),
+                )
+                .into()
+        }
+
+        OneOfBuilder::new()
+            .item(with_kind::<Coffee>())
+            .item(with_kind::<Beer>())
+            .item(with_kind::<Soda>())
+            .into()
+    }
+}
+
+impl ToSchema for Drink {
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        for (name, schema) in [
+            (Item::<Coffee>::name(), Item::<Coffee>::schema()),
+            (Item::<Beer>::name(), Item::<Beer>::schema()),
+            (Item::<Soda>::name(), Item::<Soda>::schema()),
+        ] {
+            schemas.push((name.into_owned(), schema));
+        }
+        Item::<Coffee>::schemas(schemas);
+        Item::<Beer>::schemas(schemas);
+        Item::<Soda>::schemas(schemas);
+    }
+}
+
+// Accepts what `Serialize` writes; unknown keys are ignored and a missing `id` is left empty.
+impl<'de, T: Beverage> Deserialize<'de> for Item<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ItemVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Beverage> Visitor<'de> for ItemVisitor<T> {
+            type Value = Item<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an object with an \"id\" and a \"{}\"", T::KIND)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Item<T>, A::Error> {
+                let mut id = None;
+                let mut value = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "id" {
+                        id = Some(map.next_value()?);
+                    } else if key == T::KIND {
+                        value = Some(map.next_value()?);
+                    } else {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+                Ok(Item {
+                    id: id.unwrap_or_default(),
+                    value: value.ok_or_else(|| de::Error::missing_field(T::KIND))?,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(ItemVisitor(PhantomData))
+    }
+}
+
+// A page of records, serialized as `{"<plural>": [...], "total": n, "meta": {...}}`.
+pub struct List<T> {
+    pub items: Vec<Item<T>>,
+    pub total: usize,
+    pub meta: PageMeta,
+}
+
+impl<T: Beverage> Serialize for List<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut list = serializer.serialize_struct("List", 3)?;
+        list.serialize_field(T::PLURAL, &self.items)?;
+        list.serialize_field("total", &self.total)?;
+        list.serialize_field("meta", &self.meta)?;
+        list.end()
+    }
+}
+
+impl<T: Beverage> PartialSchema for List<T> {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .property(T::PLURAL, array(schema::<Item<T>>()))
+            .property("total", ObjectBuilder::new().schema_type(Type::Integer))
+            .property("meta", schema::<PageMeta>())
+            .required(T::PLURAL)
+            .required("total")
+            .required("meta")
+            .into()
+    }
+}
+
+// Where a page sits in the whole list.  `next` and `prev` are the request's own path and
+// query with `limit` and `offset` moved on by a page; null past either end, and always for
+// `POST /search`, which takes them in the body.
+#[derive(Serialize, ToSchema)]
+pub struct PageMeta {
+    total: usize,
+    limit: usize,
+    offset: usize,
+    next: Option<String>,
+    prev: Option<String>,
+}
+
+impl<T: Beverage> ToSchema for List<T> {
+    fn name() -> std::borrow::Cow<'static, str> {
+        format!("{}List", T::name()).into()
+    }
+
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        schemas.push((Item::<T>::name().into_owned(), Item::<T>::schema()));
+        Item::<T>::schemas(schemas);
+    }
+}
+
+// `?limit=&offset=` for the list endpoints.  A missing `limit` falls back to
+// DEFAULT_PAGE_SIZE.  One above MAX_PAGE_SIZE is refused with a 400 naming the cap, rather
+// than clamped: a client clamped without noticing would take the short page for all there is.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+impl Pagination {
+    // The page selected, checked against the page sizes of `config`.
+    fn page(&self, config: &Config) -> Result<Page, AppError> {
+        let limit = self.limit.unwrap_or(config.default_page_size);
+        if limit > config.max_page_size {
+            return Err(AppError::BadRequest(format!(
+                "limit {} is over the maximum page size of {}",
+                limit, config.max_page_size
+            )));
+        }
+        Ok(Page {
+            limit,
+            offset: self.offset.unwrap_or(0),
+        })
+    }
+}
+
+// A `Pagination` that passed the checks.
+struct Page {
+    limit: usize,
+    offset: usize,
+}
+
+impl Page {
+    // The selected slice of `all`, already filtered and sorted.
+    fn select<T: Beverage>(&self, all: Vec<(Ref<T>, T)>) -> Vec<Item<T>> {
+        all.into_iter()
+            .skip(self.offset)
+            .take(self.limit)
+            .map(|(id, value)| Item {
+                id: id.to_string(),
+                value,
+            })
+            .collect()
+    }
+
+    // The links of the page this selects out of `total` records, as requested at `uri`.
+    fn meta(&self, uri: Option<&Uri>, total: usize) -> PageMeta {
+        let (offset, limit) = (self.offset, self.limit);
+        let link = |offset: usize| {
+            let uri = uri?;
+            let mut query: Vec<&str> = uri
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .filter(|pair| {
+                    let key = pair.split('=').next().unwrap_or_default();
+                    !pair.is_empty() && key != "limit" && key != "offset"
+                })
+                .collect();
+            let (limit, offset) = (format!("limit={}", limit), format!("offset={}", offset));
+            query.extend([limit.as_str(), offset.as_str()]);
+            Some(format!("{}?{}", uri.path(), query.join("&")))
+        };
+        PageMeta {
+            total,
+            limit,
+            offset,
+            // a zero limit never gets anywhere
+            next: (limit > 0 && offset + limit < total)
+                .then(|| link(offset + limit))
+                .flatten(),
+            prev: (offset > 0)
+                .then(|| link(offset.saturating_sub(limit)))
+                .flatten(),
+        }
+    }
+}
+
+// `?brand=&machine_id=&from=&to=&min_size=&max_size=&include_deleted=` for the list, count,
+// stats and CSV endpoints.  Brand matching is case-insensitive and ignores surrounding
+// whitespace; an empty (or all-whitespace) brand means no filter.  `machine_id` must match
+// exactly; without it every machine's records are returned.  `from` and `to` are inclusive
+// RFC3339 bounds on `time`, `min_size` and `max_size` inclusive bounds on `size`.
+// Soft-deleted records are left out unless `include_deleted=true`.
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListFilter {
+    brand: Option<String>,
+    machine_id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    min_size: Option<u32>,
+    max_size: Option<u32>,
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+impl ListFilter {
+    // The parsed time bounds; 400 if either is malformed or they are the wrong way round.
+    fn range(&self) -> Result<(Option<Timestamp>, Option<Timestamp>), AppError> {
+        fn bound(name: &str, value: &Option<String>) -> Result<Option<Timestamp>, AppError> {
+            match value.as_deref().filter(|value| !value.trim().is_empty()) {
+                None => Ok(None),
+                Some(value) => Timestamp::parse(value).map(Some).ok_or_else(|| {
+                    AppError::BadRequest(format!(
+                        "{} must be an RFC3339 timestamp such as 2024-03-05T10:00:00Z, got {:?}",
+                        name, value
+                    ))
+                }),
+            }
+        }
+
+        let (from, to) = (bound("from", &self.from)?, bound("to", &self.to)?);
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(AppError::BadRequest("from must not be after to".to_owned()));
+            }
+        }
+        Ok((from, to))
+    }
+
+    // The size bounds, `None` when neither is given; 400 if they are the wrong way round.
+    fn sizes(&self) -> Result<Option<RangeInclusive<u32>>, AppError> {
+        match (self.min_size, self.max_size) {
+            (None, None) => Ok(None),
+            (Some(min), Some(max)) if min > max => Err(AppError::BadRequest(
+                "min_size must not be greater than max_size".to_owned(),
+            )),
+            (min, max) => Ok(Some(min.unwrap_or(0)..=max.unwrap_or(u32::MAX))),
+        }
+    }
+
+    // Reject a malformed filter before any work starts.
+    fn check(&self) -> Result<(), AppError> {
+        self.range()?;
+        self.sizes()?;
+        Ok(())
+    }
+
+    fn brand(&self) -> Option<&str> {
+        self.brand
+            .as_deref()
+            .filter(|brand| !brand.trim().is_empty())
+    }
+
+    fn machine_id(&self) -> Option<&str> {
+        self.machine_id
+            .as_deref()
+            .filter(|machine_id| !machine_id.trim().is_empty())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum SortField {
+    Brand,
+    Size,
+    Time,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Asc,
+    Desc,
+}
+
+// `?sort=brand|size|time&order=asc|desc` for the list endpoints.  Without `sort` the newest
+// records come first; naming a field without an `order` sorts ascending.  Anything else is
+// rejected with a 400 by the query extractor.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Sorting {
+    #[param(inline)]
+    sort: Option<SortField>,
+    #[param(inline)]
+    order: Option<Order>,
+}
+
+impl Sorting {
+    // In memory, over the whole (filtered) result.  Descending reverses an ascending sort, so
+    // ties come out newest first.
+    fn sort<T: Beverage>(&self, records: &mut [(Ref<T>, T)]) {
+        let (field, default_order) = match self.sort {
+            Some(field) => (field, Order::Asc),
+            None => (SortField::Time, Order::Desc),
+        };
+        match field {
+            SortField::Brand => records.sort_by_cached_key(|(_, value)| brand_key(value.brand())),
+            SortField::Size => records.sort_by_key(|(_, value)| value.size()),
+            SortField::Time => records.sort_by_key(|(_, value)| value.time()),
+        }
+        if self.order.unwrap_or(default_order) == Order::Desc {
+            records.reverse();
+        }
+    }
+}
+
+type Records<T> = Box<dyn Iterator<Item = (Ref<T>, T)>>;
+
+// Every record of `T` the filter lets through, narrowed through one index: the brand one
+// when a brand is given, else the machine one when a machine is, else the size one when a
+// size bound is.
+fn records<T: Beverage>(file: &DbFile, filter: &ListFilter) -> Result<Records<T>, AppError> {
+    let connection = &file.connection();
+    let (from, to) = filter.range()?;
+    let sizes = filter.sizes()?;
+    let include_deleted = filter.include_deleted;
+    let machine_id = filter.machine_id().map(str::to_owned);
+    let all: Records<T> = match (filter.brand(), machine_id.as_deref(), &sizes) {
+        (Some(brand), _, _) => Box::new(T::find_by_brand(connection, brand)),
+        (None, Some(machine_id), _) => Box::new(T::find_by_machine(connection, machine_id)),
+        (None, None, Some(sizes)) => Box::new(T::find_by_size(connection, sizes.clone())),
+        (None, None, None) => Box::new(connection.scan::<T>()?),
+    };
+    Ok(Box::new(all.filter(move |(_, value)| {
+        (include_deleted || !value.is_deleted())
+            && machine_id
+                .as_deref()
+                .is_none_or(|id| value.machine_id() == id)
+            && sizes
+                .as_ref()
+                .is_none_or(|sizes| sizes.contains(&value.size()))
+            && from.is_none_or(|from| value.time() >= from)
+            && to.is_none_or(|to| value.time() <= to)
+    })))
+}
+
+// Optimistic concurrency: a write must name the version it read, and loses if the record has
+// moved on since.
+fn check_version<T: Beverage>(id: &str, given: Option<u64>, stored: &T) -> Result<(), AppError> {
+    match given {
+        None | Some(0) => Err(AppError::Validation(vec!["version is required".to_owned()])),
+        Some(version) if version != stored.version() => Err(AppError::Conflict(format!(
+            "{} {} is at version {}, not {}",
+            T::KIND,
+            id,
+            stored.version(),
+            version
+        ))),
+        Some(_) => Ok(()),
+    }
+}
+
+// Two records of one brand stamped with the same `time` are the same drink entered twice, so
+// the second insert loses.  Soft-deleted records count, as they can be restored.  Call it in
+// the inserting transaction, right before the insert.
+pub fn check_unique<T: Beverage>(tx: &mut OwnedSytx, value: &T) -> Result<(), AppError> {
+    let time = value.time();
+    if T::find_by_brand_tx(tx, value.brand()).any(|(_, stored)| stored.time() == time) {
+        return Err(AppError::Conflict(format!(
+            "a {} of brand {:?} with time {} already exists",
+            T::KIND,
+            value.brand(),
+            time.to_datetime().to_rfc3339()
+        )));
+    }
+    Ok(())
+}
+
+// A read as the handlers see it: soft-deleted records are as good as missing.
+fn live<T: Beverage>(value: Option<T>) -> Option<T> {
+    value.filter(|value| !value.is_deleted())
+}
+
+fn not_found<T: Beverage>(id: &str) -> AppError {
+    AppError::NotFound(format!("{} {} not found", T::KIND, id))
+}
+
+// With an `Idempotency-Key` header, repeating a create returns the record the first request
+// made (flagged by `Idempotent-Replayed: true`) instead of inserting another one.  The body of
+// the repeat is not compared with the original.  `?dry_run=true` validates and checks for
+// duplicates, then answers 200 with the record as it would be stored, without an id.
+async fn create<T: Beverage>(
+    State(state): State<AppState>,
+    request_id: RequestId,
+    headers: HeaderMap,
+    AppQuery(DryRun { dry_run }): AppQuery<DryRun>,
+    AppJson(create): AppJson<T::Create>,
+) -> Result<Response, AppError> {
+    let result = async {
+        let mut value = T::from_create(create, Timestamp::now());
+        value.validate().map_err(AppError::Validation)?;
+        value.refresh_derived();
+        if dry_run {
+            // no insert, so no id either: only the record as it would be stored
+            return run_blocking(move || {
+                with_tx_or_dry_run(state.db.of::<T>(), true, |tx| check_unique(tx, &value))?;
+                Ok(AppJson(value).into_response())
+            })
+            .await;
+        }
+        let key = idempotency::key(&headers);
+        run_blocking(move || {
+            // the id, and the stored record when this is a replay
+            let (id, replayed) = with_tx(state.db.of::<T>(), |tx| {
+                if let Some(key) = &key {
+                    let ttl = state.config.idempotency_ttl;
+                    if let Some((record, id)) = idempotency::lookup(tx, T::KIND, key, ttl)? {
+                        let p_id: Ref<T> = parse_ref(&id)?;
+                        match live(tx.read(&p_id)?) {
+                            Some(stored) => return Ok((id, Some(stored))),
+                            // deleted since; the key is free to create a new record
+                            None => tx.delete(&record)?,
+                        }
+                    }
+                }
+                check_unique(tx, &value)?;
+                let id = tx.insert(&value)?.to_string();
+                audit::record(tx, "create", T::KIND, &id, &request_id)?;
+                if let Some(key) = &key {
+                    tx.insert(&IdempotencyRecord::new(T::KIND, key, &id))?;
+                }
+                Ok((id, None))
+            })?;
+            let mut headers = HeaderMap::new();
+            headers.insert(header::LOCATION, location::<T>(&id));
+            let value = match replayed {
+                Some(stored) => {
+                    headers.insert("idempotent-replayed", HeaderValue::from_static("true"));
+                    stored
+                }
+                None => value,
+            };
+            let item = Item { id, value };
+            if !headers.contains_key("idempotent-replayed") {
+                state.events.created(&item);
+            }
+            Ok((StatusCode::CREATED, headers, AppJson(item)).into_response())
+        })
+        .await
+    }
+    .await;
+    if !dry_run {
+        metrics::count_write(T::KIND, "create", &result);
+    }
+    result
+}
+
+fn location<T: Beverage>(id: &str) -> HeaderValue {
+    HeaderValue::try_from(format!("{}/{}/{}", API_PREFIX, T::KIND, id))
+        .expect("ids are valid header values")
+}
+
+// Insert every drink in one transaction, or none of them.  Validation problems are reported
+// together, each prefixed with the index of the offending item.  The items share one `time`, so
+// two of the same brand are rejected by `check_unique`.
+async fn create_batch<T: Beverage>(
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(creates): AppJson<Vec<T::Create>>,
+) -> Result<(StatusCode, AppJson<Vec<Item<T>>>), AppError> {
+    let result = async {
+        let now = Timestamp::now();
+        let mut values: Vec<T> = creates
+            .into_iter()
+            .map(|create| T::from_create(create, now))
+            .collect();
+        let problems: Vec<String> = values
+            .iter()
+            .enumerate()
+            .filter_map(|(index, value)| value.validate().err().map(|errs| (index, errs)))
+            .flat_map(|(index, errs)| errs.into_iter().map(move |e| format!("[{}] {}", index, e)))
+            .collect();
+        if !problems.is_empty() {
+            return Err(AppError::Validation(problems));
+        }
+        for value in &mut values {
+            value.refresh_derived();
+        }
+        run_blocking(move || {
+            let ids = with_tx(state.db.of::<T>(), |tx| {
+                let mut ids = Vec::with_capacity(values.len());
+                for value in &values {
+                    check_unique(tx, value)?;
+                    let id = tx.insert(value)?.to_string();
+                    audit::record(tx, "create", T::KIND, &id, &request_id)?;
+                    ids.push(id);
+                }
+                Ok(ids)
+            })?;
+            let items = ids
+                .into_iter()
+                .zip(values)
+                .map(|(id, value)| Item { id, value })
+                .collect::<Vec<_>>();
+            for item in &items {
+                state.events.created(item);
+            }
+            Ok((StatusCode::CREATED, AppJson(items)))
+        })
+        .await
+    }
+    .await;
+    metrics::count_write(T::KIND, "batch_create", &result);
+    result
+}
+
+// `?fields=brand,size` on the read endpoints: only these fields of each record are sent.  The
+// id always is.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Fields {
+    fields: Option<String>,
+}
+
+impl Fields {
+    // The requested names, or `None` for every field; 400 on a name `T` doesn't have.
+    fn names<T: Beverage>(&self) -> Result<Option<Vec<String>>, AppError> {
+        let Some(fields) = self.fields.as_deref().filter(|f| !f.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let names: Vec<String> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let unknown: Vec<&str> = names
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !T::FIELDS.contains(name))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "unknown field(s) {}; {} has {}",
+                unknown.join(", "),
+                T::KIND,
+                T::FIELDS.join(", ")
+            )));
+        }
+        Ok(Some(names))
+    }
+}
+
+// Drop every key of a serialized record that isn't in `names`.
+fn project(record: &mut serde_json::Value, names: &[String]) {
+    if let Some(record) = record.as_object_mut() {
+        record.retain(|key, _| names.contains(key));
+    }
+}
+
+fn to_json<B: Serialize>(body: &B) -> Result<serde_json::Value, AppError> {
+    serde_json::to_value(body).map_err(|err| AppError::IOError(err.into()))
+}
+
+fn sparse_list<T: Beverage>(list: &List<T>, names: &[String]) -> Result<Response, AppError> {
+    let mut body = to_json(list)?;
+    if let Some(items) = body[T::PLURAL].as_array_mut() {
+        for item in items {
+            project(&mut item[T::KIND], names);
+        }
+    }
+    Ok(AppJson(body).into_response())
+}
+
+// Representations of a list, picked from the `Accept` header.
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    const SUPPORTED: &'static str = "application/json, text/csv";
+
+    fn of(media_range: &str) -> Option<Format> {
+        match media_range {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "text/csv" | "text/*" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    // The supported type with the highest `q`, the earliest listed on a tie.  No `Accept`
+    // header means JSON; one naming nothing supported is a 406.
+    fn negotiate(headers: &HeaderMap) -> Result<Format, AppError> {
+        let ranges: Vec<_> = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .collect();
+        if ranges.is_empty() {
+            return Ok(Format::Json);
+        }
+        let mut best: Option<(f32, Format)> = None;
+        for range in &ranges {
+            let mut parts = range.split(';').map(str::trim);
+            let media = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if let Some(format) = Format::of(&media).filter(|_| q > 0.0) {
+                if best.is_none_or(|(best_q, _)| q > best_q) {
+                    best = Some((q, format));
+                }
+            }
+        }
+        best.map(|(_, format)| format).ok_or_else(|| {
+            AppError::NotAcceptable(format!(
+                "cannot answer with {}; supported: {}",
+                ranges.join(", "),
+                Format::SUPPORTED
+            ))
+        })
+    }
+}
+
+// Columns of a CSV download after `id`, unless `?fields=` picks others.
+const CSV_COLUMNS: &[&str] = &["brand", "machine_id", "size", "time"];
+
+// Items as CSV rows under an `id,<columns>` header.  Strings go in unquoted where the format
+// allows, absent values as empty cells.
+fn to_csv<T: Beverage>(
+    items: impl IntoIterator<Item = Item<T>>,
+    columns: &[&str],
+) -> Result<Vec<u8>, AppError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(std::iter::once("id").chain(columns.iter().copied()))
+        .map_err(std::io::Error::from)?;
+    for item in items {
+        let value = to_json(&item.value)?;
+        let cells = columns.iter().map(|column| match &value[*column] {
+            serde_json::Value::String(cell) => cell.clone(),
+            serde_json::Value::Null => String::new(),
+            cell => cell.to_string(),
+        });
+        writer
+            .write_record(std::iter::once(item.id).chain(cells))
+            .map_err(std::io::Error::from)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|err| AppError::IOError(err.into_error()))
+}
+
+// A page of records as JSON, or as CSV for `Accept: text/csv`.
+async fn list<T: Beverage>(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    AppQuery(pagination): AppQuery<Pagination>,
+    AppQuery(filter): AppQuery<ListFilter>,
+    AppQuery(sorting): AppQuery<Sorting>,
+    AppQuery(fields): AppQuery<Fields>,
+) -> Result<Response, AppError> {
+    let format = Format::negotiate(&headers)?;
+    let names = fields.names::<T>()?;
+    let page = pagination.page(&state.config)?;
+    let include_deleted = filter.include_deleted;
+    let filter = ListFilter {
+        include_deleted: true,
+        ..filter
+    };
+    let (list, modified) = run_blocking(move || {
+        // Deleted records count towards `Last-Modified` even when they aren't listed, so a
+        // delete moves it on like any other write.
+        let mut all: Vec<_> = records::<T>(state.db.of::<T>(), &filter)?.collect();
+        let modified = all.iter().map(|(_, value)| value.updated_at()).max();
+        if !include_deleted {
+            all.retain(|(_, value)| !value.is_deleted());
+        }
+        sorting.sort(&mut all);
+        let total = all.len();
+        let items = page.select(all);
+        let meta = page.meta(Some(&uri), total);
+        Ok((List { items, total, meta }, modified))
+    })
+    .await?;
+    if let Some(since) = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Timestamp::parse_http_date)
+    {
+        if modified.is_some_and(|modified| modified.whole_seconds() <= since) {
+            return Ok(not_modified(modified));
+        }
+    }
+    let mut response = match (format, names) {
+        (Format::Csv, names) => {
+            let columns = names.as_ref().map_or(CSV_COLUMNS.to_vec(), |names| {
+                names.iter().map(String::as_str).collect()
+            });
+            let body = to_csv(list.items, &columns)?;
+            ([(header::CONTENT_TYPE, "text/csv")], body).into_response()
+        }
+        (Format::Json, Some(names)) => sparse_list(&list, &names)?,
+        (Format::Json, None) => AppJson(list).into_response(),
+    };
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    set_last_modified(&mut response, modified);
+    Ok(response)
+}
+
+// `Last-Modified` is the newest `updated_at` of the matching records, and left out when there
+// are none.  HTTP dates have whole seconds, so two writes within the same second of a fetch
+// look alike to `If-Modified-Since`.
+fn set_last_modified(response: &mut Response, modified: Option<Timestamp>) {
+    if let Some(value) = modified.and_then(|time| HeaderValue::from_str(&time.to_http_date()).ok())
+    {
+        response.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+}
+
+fn not_modified(modified: Option<Timestamp>) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    set_last_modified(&mut response, modified);
+    response
+}
+
+// Body of `POST /<kind>/search`: the list's query parameters as one JSON object, for filters
+// that would make an unwieldy query string.  Each field means what the parameter of the same
+// name does, and all are optional.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct SearchRequest {
+    brand: Option<String>,
+    machine_id: Option<String>,
+    // RFC3339
+    from: Option<String>,
+    to: Option<String>,
+    min_size: Option<u32>,
+    max_size: Option<u32>,
+    #[serde(default)]
+    include_deleted: bool,
+    sort: Option<SortField>,
+    order: Option<Order>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+impl SearchRequest {
+    fn split(self) -> (ListFilter, Sorting, Pagination) {
+        (
+            ListFilter {
+                brand: self.brand,
+                machine_id: self.machine_id,
+                from: self.from,
+                to: self.to,
+                min_size: self.min_size,
+                max_size: self.max_size,
+                include_deleted: self.include_deleted,
+            },
+            Sorting {
+                sort: self.sort,
+                order: self.order,
+            },
+            Pagination {
+                limit: self.limit,
+                offset: self.offset,
+            },
+        )
+    }
+}
+
+// The list, queried with a JSON body instead of the query string.  It goes through the same
+// index choice as `list`: brand, then machine, then size.
+async fn search<T: Beverage>(
+    State(state): State<AppState>,
+    AppJson(request): AppJson<SearchRequest>,
+) -> Result<AppJson<List<T>>, AppError> {
+    let (filter, sorting, pagination) = request.split();
+    let page = pagination.page(&state.config)?;
+    run_blocking(move || {
+        let mut all: Vec<_> = records::<T>(state.db.of::<T>(), &filter)?.collect();
+        sorting.sort(&mut all);
+        let total = all.len();
+        let items = page.select(all);
+        let meta = page.meta(None, total);
+        Ok(AppJson(List { items, total, meta }))
+    })
+    .await
+}
+
+// Rows buffered between the scan and a slow client before the scan waits.
+const STREAM_BUFFER: usize = 64;
+
+// `GET /<kind>/stream`: the (optionally brand filtered) records as NDJSON, one item per line.
+// The scan runs on the blocking pool and hands lines over a bounded channel, so memory stays
+// flat whatever the table size.  A failure halfway through aborts the response.
+async fn stream<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    // a bad filter should be a 400, not a stream that aborts straight away
+    filter.check()?;
+    let (lines, rx) = mpsc::channel::<Result<Vec<u8>, AppError>>(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let records = match records::<T>(state.db.of::<T>(), &filter) {
+            Ok(records) => records,
+            Err(err) => {
+                let _ = lines.blocking_send(Err(err));
+                return;
+            }
+        };
+        for (id, value) in records {
+            let item = Item {
+                id: id.to_string(),
+                value,
+            };
+            let line = serde_json::to_vec(&item)
+                .map(|mut line| {
+                    line.push(b'\n');
+                    line
+                })
+                .map_err(|err| AppError::IOError(err.into()));
+            // stop scanning once the client has gone away
+            if lines.blocking_send(line).is_err() {
+                return;
+            }
+        }
+    });
+    let body = Body::from_stream(ReceiverStream::new(rx).map(|line| {
+        line.map_err(|err| {
+            tracing::error!("stream aborted -> {}", err);
+            err.to_string()
+        })
+    }));
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body))
+}
+
+// `GET /<kind>/export.csv`: the (optionally brand filtered) list as a spreadsheet download.
+// Unlike `list` with `Accept: text/csv` it isn't paginated.
+async fn export_csv<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    let body = run_blocking(move || {
+        let items = records::<T>(state.db.of::<T>(), &filter)?.map(|(id, value)| Item {
+            id: id.to_string(),
+            value,
+        });
+        to_csv(items, CSV_COLUMNS)
+    })
+    .await?;
+    let disposition = format!("attachment; filename=\"{}.csv\"", T::PLURAL);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv".to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+#[derive(Serialize, ToSchema)]
+struct Drinks {
+    drinks: Vec<Drink>,
+    total: usize,
+    meta: PageMeta,
+}
+
+// Every drink in the machine: coffees, then beers, then sodas, paginated and brand filtered
+// as one list.
+#[utoipa::path(
+    get,
+    path = "/drinks",
+    tag = "drinks",
+    params(Pagination, ListFilter),
+    responses(
+        (status = 200, description = "A page of drinks of every type", body = Drinks),
+        (
+            status = 400,
+            description = "A malformed filter, or a limit over MAX_PAGE_SIZE",
+            body = crate::problem::Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn drinks(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    AppQuery(pagination): AppQuery<Pagination>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<AppJson<Drinks>, AppError> {
+    let page = pagination.page(&state.config)?;
+    let (offset, limit) = (page.offset, page.limit);
+    run_blocking(move || {
+        let db = &state.db;
+        let all = records::<Coffee>(db.of::<Coffee>(), &filter)?
+            .map(|(id, value)| {
+                Drink::Coffee(Item {
+                    id: id.to_string(),
+                    value,
+                })
+            })
+            .chain(
+                records::<Beer>(db.of::<Beer>(), &filter)?.map(|(id, value)| {
+                    Drink::Beer(Item {
+                        id: id.to_string(),
+                        value,
+                    })
+                }),
+            )
+            .chain(
+                records::<Soda>(db.of::<Soda>(), &filter)?.map(|(id, value)| {
+                    Drink::Soda(Item {
+                        id: id.to_string(),
+                        value,
+                    })
+                }),
+            );
+        let mut drinks = Vec::new();
+        let mut total = 0;
+        for drink in all {
+            if total >= offset && drinks.len() < limit {
+                drinks.push(drink);
+            }
+            total += 1;
+        }
+        let meta = page.meta(Some(&uri), total);
+        Ok(AppJson(Drinks {
+            drinks,
+            total,
+            meta,
+        }))
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct Stats {
+    count: usize,
+    // null when there is nothing to average
+    avg_size: Option<f64>,
+    total_size: u64,
+    // distinct brands, compared like the brand filter does
+    brands: usize,
+}
+
+async fn stats<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<AppJson<Stats>, AppError> {
+    run_blocking(move || {
+        let mut count = 0;
+        let mut total_size = 0;
+        let mut brands = HashSet::new();
+        for (_, value) in records::<T>(state.db.of::<T>(), &filter)? {
+            count += 1;
+            total_size += u64::from(value.size());
+            brands.insert(brand_key(value.brand()));
+        }
+        Ok(AppJson(Stats {
+            count,
+            avg_size: (count > 0).then(|| total_size as f64 / count as f64),
+            total_size,
+            brands: brands.len(),
+        }))
+    })
+    .await
+}
+
+// `{"<brand>": count, ...}`, written in order: biggest brand first, ties alphabetical.
+#[derive(ToSchema)]
+#[schema(value_type = HashMap<String, usize>)]
+struct ByBrand(Vec<(String, usize)>);
+
+impl Serialize for ByBrand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(brand, count)| (brand, count)))
+    }
+}
+
+// Records per brand.  Brands are grouped like the brand filter matches them and shown as
+// first spelled.
+async fn by_brand<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<AppJson<ByBrand>, AppError> {
+    run_blocking(move || {
+        let mut groups: HashMap<String, (String, usize)> = HashMap::new();
+        for (_, value) in records::<T>(state.db.of::<T>(), &filter)? {
+            groups
+                .entry(brand_key(value.brand()))
+                .or_insert_with(|| (value.brand().to_owned(), 0))
+                .1 += 1;
+        }
+        let mut counts: Vec<_> = groups.into_values().collect();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+        Ok(AppJson(ByBrand(counts)))
+    })
+    .await
+}
+
+// The brands in use, for a dropdown: one per brand as `by_brand` groups them, shown as first
+// spelled, in alphabetical order.  Takes the same filters, `machine_id` among them.
+async fn brands<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<AppJson<Vec<String>>, AppError> {
+    run_blocking(move || {
+        let mut brands: BTreeMap<String, String> = BTreeMap::new();
+        for (_, value) in records::<T>(state.db.of::<T>(), &filter)? {
+            brands
+                .entry(brand_key(value.brand()))
+                .or_insert_with(|| value.brand().to_owned());
+        }
+        Ok(AppJson(brands.into_values().collect()))
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct Count {
+    count: usize,
+}
+
+async fn count<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<AppJson<Count>, AppError> {
+    run_blocking(move || {
+        let count = records::<T>(state.db.of::<T>(), &filter)?.count();
+        Ok(AppJson(Count { count }))
+    })
+    .await
+}
+
+// `?threshold=` for the low-stock list, overriding `LOW_STOCK_THRESHOLD`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LowStockParams {
+    threshold: Option<u32>,
+}
+
+// Live records with `threshold` units or fewer, emptiest first, for a "needs restocking"
+// dashboard.
+async fn low_stock<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(params): AppQuery<LowStockParams>,
+) -> Result<AppJson<Vec<Item<T>>>, AppError> {
+    let threshold = params.threshold.unwrap_or(state.config.low_stock_threshold);
+    run_blocking(move || {
+        let mut low: Vec<_> = records::<T>(state.db.of::<T>(), &ListFilter::default())?
+            .filter(|(_, value)| value.stock() <= threshold)
+            .collect();
+        low.sort_by_key(|(_, value)| value.stock());
+        let items = low
+            .into_iter()
+            .map(|(id, value)| Item {
+                id: id.to_string(),
+                value,
+            })
+            .collect();
+        Ok(AppJson(items))
+    })
+    .await
+}
+
+// `?limit=` for the trending list, clamped to MAX_PAGE_SIZE: it is a top-N, not a page.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TrendingParams {
+    limit: Option<usize>,
+}
+
+const DEFAULT_TRENDING: usize = 5;
+
+// The most purchased records, most first.  Ties go to the brand first in alphabetical order
+// (compared like the brand filter), then to the newer record.  Soft-deleted records are left
+// out.
+async fn trending<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(params): AppQuery<TrendingParams>,
+) -> Result<AppJson<Vec<Item<T>>>, AppError> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_TRENDING)
+        .min(state.config.max_page_size);
+    run_blocking(move || {
+        let mut all: Vec<_> = records::<T>(state.db.of::<T>(), &ListFilter::default())?
+            .map(|(id, value)| (brand_key(value.brand()), id, value))
+            .collect();
+        let rank = |a: &(String, Ref<T>, T), b: &(String, Ref<T>, T)| {
+            b.2.purchases()
+                .cmp(&a.2.purchases())
+                .then_with(|| a.0.cmp(&b.0))
+                .then_with(|| b.2.time().cmp(&a.2.time()))
+        };
+        // only the top `limit` need to be in order
+        if limit < all.len() {
+            all.select_nth_unstable_by(limit, rank);
+            all.truncate(limit);
+        }
+        all.sort_by(rank);
+        Ok(AppJson(
+            all.into_iter()
+                .map(|(_, id, value)| Item {
+                    id: id.to_string(),
+                    value,
+                })
+                .collect(),
+        ))
+    })
+    .await
+}
+
+// "Surprise me": one record picked uniformly at random from those the filter lets through.
+async fn random<T: Beverage>(
+    State(state): State<AppState>,
+    AppQuery(filter): AppQuery<ListFilter>,
+) -> Result<AppJson<Item<T>>, AppError> {
+    run_blocking(move || {
+        let picked = records::<T>(state.db.of::<T>(), &filter)?.choose(&mut rand::thread_rng());
+        match picked {
+            Some((id, value)) => Ok(AppJson(Item {
+                id: id.to_string(),
+                value,
+            })),
+            None => Err(AppError::NotFound(format!("no {} to pick from", T::KIND))),
+        }
+    })
+    .await
+}
+
+// `?include_deleted=true` on a single-record read.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ShowDeleted {
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+// `?dry_run=true` on a create or update: validate and check it against the stored records,
+// but write nothing.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DryRun {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// Strong validator of a response body.  Every write bumps the record's version and so changes
+// the body; hashing the body rather than using the version also tells `?fields=` selections
+// apart.
+fn etag(body: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))
+        .expect("a quoted hex string is a valid header value")
+}
+
+// Whether `If-None-Match` lists `etag`, or is `*`.  Weak comparison, as RFC 9110 asks for
+// this header, so a `W/` prefix is ignored.
+fn none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or_default();
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// A record, with an `ETag`; `304 Not Modified` with no body when the client's copy is current.
+async fn fetch<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    AppQuery(show): AppQuery<ShowDeleted>,
+    AppQuery(fields): AppQuery<Fields>,
+) -> Result<Response, AppError> {
+    let p_id: structsy::Ref<T> = parse_ref(&id)?;
+    let names = fields.names::<T>()?;
+    let item = run_blocking(move || match state.db.of::<T>().connection().read(&p_id)? {
+        Some(value) if show.include_deleted || !value.is_deleted() => Ok(Item { id, value }),
+        _ => Err(not_found::<T>(&id)),
+    })
+    .await?;
+    let mut body = to_json(&item)?;
+    if let Some(names) = names {
+        project(&mut body[T::KIND], &names);
+    }
+    let body = serde_json::to_vec(&body).map_err(|err| AppError::IOError(err.into()))?;
+    let tag = etag(&body);
+    if none_match(&headers, &tag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, tag)]).into_response());
+    }
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (header::ETAG, tag),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+// With `?dry_run=true`, nothing is written and the record as it would be stored is returned.
+async fn update<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppQuery(DryRun { dry_run }): AppQuery<DryRun>,
+    AppJson(mut value): AppJson<T>,
+) -> Result<Response, AppError> {
+    let result = async {
+        value.validate().map_err(AppError::Validation)?;
+        value.refresh_derived();
+        let p_id: structsy::Ref<T> = parse_ref(&id)?;
+        let given = value.version();
+        run_blocking(move || {
+            with_tx_or_dry_run(state.db.of::<T>(), dry_run, |tx| {
+                let Some(stored) = live(tx.read(&p_id)?) else {
+                    return Err(not_found::<T>(&id));
+                };
+                check_version(&id, Some(given), &stored)?;
+                value.set_time(stored.time());
+                value.set_purchases(stored.purchases());
+                value.set_deleted_at(None);
+                value.set_version(stored.version() + 1);
+                if !dry_run {
+                    tx.update(&p_id, &value)?;
+                    audit::record(tx, "update", T::KIND, &id, &request_id)?;
+                }
+                Ok(())
+            })?;
+            Ok(if dry_run {
+                AppJson(Item { id, value }).into_response()
+            } else {
+                ().into_response()
+            })
+        })
+        .await
+    }
+    .await;
+    if !dry_run {
+        metrics::count_write(T::KIND, "update", &result);
+    }
+    result
+}
+
+// A PATCH body: the fields to change next to the version they were read at.
+struct Versioned<P> {
+    version: Option<u64>,
+    patch: P,
+}
+
+// The patch's fields plus `version`.
+fn versioned_schema<P: ToSchema>() -> RefOr<Schema> {
+    AllOfBuilder::new()
+        .item(schema::<P>())
+        .item(
+            ObjectBuilder::new()
+                .property("version", ObjectBuilder::new().schema_type(Type::Integer)),
+        )
+        .into()
+}
+
+// By hand rather than with `#[serde(flatten)]`, which would hide unknown keys from the patch's
+// `deny_unknown_fields`.
+impl<'de, P: DeserializeOwned> Deserialize<'de> for Versioned<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut body = serde_json::Map::deserialize(deserializer)?;
+        let version = body
+            .remove("version")
+            .map(serde_json::from_value::<Option<u64>>)
+            .transpose()
+            .map_err(de::Error::custom)?
+            .flatten();
+        let patch = P::deserialize(serde_json::Value::Object(body)).map_err(de::Error::custom)?;
+        Ok(Versioned { version, patch })
+    }
+}
+
+// Partial update: read, apply the given fields and write back in one transaction, then
+// return the record as stored (or as it would be, with `?dry_run=true`).
+async fn patch_one<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppQuery(DryRun { dry_run }): AppQuery<DryRun>,
+    AppJson(Versioned { version, patch }): AppJson<Versioned<T::Patch>>,
+) -> Result<AppJson<Item<T>>, AppError> {
+    let result = async {
+        let p_id: structsy::Ref<T> = parse_ref(&id)?;
+        run_blocking(move || {
+            let value = with_tx_or_dry_run(state.db.of::<T>(), dry_run, |tx| {
+                let Some(mut value) = live(tx.read(&p_id)?) else {
+                    return Err(not_found::<T>(&id));
+                };
+                check_version(&id, version, &value)?;
+                value.apply(patch.clone());
+                value.set_version(value.version() + 1);
+                value.validate().map_err(AppError::Validation)?;
+                value.refresh_derived();
+                if !dry_run {
+                    tx.update(&p_id, &value)?;
+                    audit::record(tx, "update", T::KIND, &id, &request_id)?;
+                }
+                Ok(value)
+            })?;
+            Ok(AppJson(Item { id, value }))
+        })
+        .await
+    }
+    .await;
+    if !dry_run {
+        metrics::count_write(T::KIND, "update", &result);
+    }
+    result
+}
+
+// Soft delete: the record stays stored, flagged with `deleted_at`, and reads skip it.
+async fn remove<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+) -> Result<(), AppError> {
+    let result = async {
+        let p_id: structsy::Ref<T> = parse_ref(&id)?;
+        run_blocking(move || {
+            with_tx(state.db.of::<T>(), |tx| {
+                let Some(mut value) = live(tx.read(&p_id)?) else {
+                    return Err(not_found::<T>(&id));
+                };
+                soft_delete(&mut value);
+                tx.update(&p_id, &value)?;
+                audit::record(tx, "delete", T::KIND, &id, &request_id)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+    .await;
+    metrics::count_write(T::KIND, "delete", &result);
+    result
+}
+
+// Hide a record from reads without losing it; `restore` brings it back.
+fn soft_delete<T: Beverage>(value: &mut T) {
+    value.set_deleted_at(Some(Timestamp::now()));
+    value.set_version(value.version() + 1);
+}
+
+// `POST /<kind>/:id/restore`: clear the deleted flag and return the record.  Restoring a
+// record that isn't deleted changes nothing.
+async fn restore<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+) -> Result<AppJson<Item<T>>, AppError> {
+    let result = async {
+        let p_id: structsy::Ref<T> = parse_ref(&id)?;
+        run_blocking(move || {
+            let value = with_tx(state.db.of::<T>(), |tx| {
+                let Some(mut value) = tx.read(&p_id)? else {
+                    return Err(not_found::<T>(&id));
+                };
+                if value.is_deleted() {
+                    value.set_deleted_at(None);
+                    value.set_version(value.version() + 1);
+                    tx.update(&p_id, &value)?;
+                    audit::record(tx, "restore", T::KIND, &id, &request_id)?;
+                }
+                Ok(value)
+            })?;
+            Ok(AppJson(Item { id, value }))
+        })
+        .await
+    }
+    .await;
+    metrics::count_write(T::KIND, "restore", &result);
+    result
+}
+
+#[derive(Serialize, ToSchema)]
+struct DeleteSummary {
+    deleted: usize,
+    not_found: Vec<String>,
+    invalid: Vec<String>,
+}
+
+// Soft-delete every listed id in one transaction.  Neither a missing (or already deleted)
+// record nor a malformed id fails the request: the former are reported in `not_found`, the
+// latter in `invalid`, and everything else is deleted.
+async fn delete_batch<T: Beverage>(
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(ids): AppJson<Vec<String>>,
+) -> Result<AppJson<DeleteSummary>, AppError> {
+    let result = run_blocking(move || {
+        let summary = with_tx(state.db.of::<T>(), |tx| {
+            let mut summary = DeleteSummary {
+                deleted: 0,
+                not_found: Vec::new(),
+                invalid: Vec::new(),
+            };
+            for id in &ids {
+                let Ok(p_id) = parse_ref::<T>(id) else {
+                    summary.invalid.push(id.clone());
+                    continue;
+                };
+                let Some(mut value) = live(tx.read(&p_id)?) else {
+                    summary.not_found.push(id.clone());
+                    continue;
+                };
+                soft_delete(&mut value);
+                tx.update(&p_id, &value)?;
+                audit::record(tx, "delete", T::KIND, id, &request_id)?;
+                summary.deleted += 1;
+            }
+            Ok(summary)
+        })?;
+        Ok(AppJson(summary))
+    })
+    .await;
+    metrics::count_write(T::KIND, "batch_delete", &result);
+    result
+}
+
+#[derive(Serialize, ToSchema)]
+struct Discontinued {
+    deleted: usize,
+}
+
+// Discontinue a product: soft-delete every live record of `brand`, matched like the `?brand=`
+// filter, in one transaction.  No match is not an error, the count is just 0.
+async fn delete_by_brand<T: Beverage>(
+    Path(brand): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+) -> Result<AppJson<Discontinued>, AppError> {
+    let result = run_blocking(move || {
+        let deleted = with_tx(state.db.of::<T>(), |tx| {
+            let found: Vec<_> = T::find_by_brand_tx(tx, &brand)
+                .filter(|(_, value)| !value.is_deleted())
+                .collect();
+            let count = found.len();
+            for (p_id, mut value) in found {
+                soft_delete(&mut value);
+                tx.update(&p_id, &value)?;
+                audit::record(tx, "delete", T::KIND, &p_id.to_string(), &request_id)?;
+            }
+            Ok(count)
+        })?;
+        tracing::info!(
+            "discontinued {} {} records of brand {:?}",
+            deleted,
+            T::KIND,
+            brand
+        );
+        Ok(AppJson(Discontinued { deleted }))
+    })
+    .await;
+    metrics::count_write(T::KIND, "delete_by_brand", &result);
+    result
+}
+
+// `POST /<kind>/rename-brand` body.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct RenameBrand {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Renamed {
+    updated: usize,
+}
+
+// A rebrand: every record of brand `from`, matched like the `?brand=` filter and deleted ones
+// included so a restore doesn't bring the old name back, becomes brand `to` in one
+// transaction.
+async fn rename_brand<T: Beverage>(
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(RenameBrand { from, to }): AppJson<RenameBrand>,
+) -> Result<AppJson<Renamed>, AppError> {
+    let result = async {
+        if to.trim().is_empty() {
+            return Err(AppError::Validation(
+                vec!["to must not be empty".to_owned()],
+            ));
+        }
+        run_blocking(move || {
+            let updated = with_tx(state.db.of::<T>(), |tx| {
+                let found: Vec<_> = T::find_by_brand_tx(tx, &from).collect();
+                let count = found.len();
+                // only a change of case or spacing: the records would clash with themselves
+                let same_key = brand_key(&from) == brand_key(&to);
+                for (p_id, mut value) in found {
+                    value.set_brand(to.clone());
+                    value.refresh_derived();
+                    if !same_key {
+                        check_unique(tx, &value)?;
+                    }
+                    value.set_version(value.version() + 1);
+                    tx.update(&p_id, &value)?;
+                    audit::record(tx, "rename", T::KIND, &p_id.to_string(), &request_id)?;
+                }
+                Ok(count)
+            })?;
+            tracing::info!(
+                "renamed {} {} records from {:?} to {:?}",
+                updated,
+                T::KIND,
+                from,
+                to
+            );
+            Ok(AppJson(Renamed { updated }))
+        })
+        .await
+    }
+    .await;
+    metrics::count_write(T::KIND, "rename_brand", &result);
+    result
+}
+
+// `POST /<kind>/prices` body.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct PriceChange {
+    // only records of this brand, matched like the `?brand=` filter; every live record without
+    // it
+    brand: Option<String>,
+    // 10 raises every price by a tenth
+    percent: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Repriced {
+    updated: usize,
+    // the lowest and highest of the new prices; null when nothing matched
+    min_price_cents: Option<u32>,
+    max_price_cents: Option<u32>,
+}
+
+// Raise the prices of a brand, or of every live record, by a percentage in one transaction,
+// rounding each to the nearest cent.  Deleted records keep their price, like they are left out
+// of `delete_by_brand`.  Prices only go up: a negative percent is refused, and so is one that
+// would take a price past what a record can hold.
+async fn reprice<T: Beverage>(
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(PriceChange { brand, percent }): AppJson<PriceChange>,
+) -> Result<AppJson<Repriced>, AppError> {
+    let result = async {
+        if !(percent >= 0.0 && percent.is_finite()) {
+            return Err(AppError::Validation(vec![format!(
+                "percent must be 0 or more, got {}",
+                percent
+            )]));
+        }
+        let factor = 1.0 + percent / 100.0;
+        run_blocking(move || {
+            let repriced = with_tx(state.db.of::<T>(), |tx| {
+                let found: Vec<_> = match brand.as_deref().filter(|brand| !brand.trim().is_empty())
+                {
+                    Some(brand) => T::find_by_brand_tx(tx, brand)
+                        .filter(|(_, value)| !value.is_deleted())
+                        .collect(),
+                    None => tx
+                        .scan::<T>()?
+                        .filter(|(_, value)| !value.is_deleted())
+                        .collect(),
+                };
+                let mut repriced = Repriced {
+                    updated: found.len(),
+                    min_price_cents: None,
+                    max_price_cents: None,
+                };
+                for (p_id, mut value) in found {
+                    let price = (f64::from(value.price_cents()) * factor).round();
+                    if price > f64::from(u32::MAX) {
+                        return Err(AppError::Validation(vec![format!(
+                            "{} {} would cost {} cents, more than the {} a price can be",
+                            T::KIND,
+                            p_id,
+                            price,
+                            u32::MAX
+                        )]));
+                    }
+                    // in range and whole, checked above
+                    let price = price as u32;
+                    value.set_price_cents(price);
+                    value.set_version(value.version() + 1);
+                    tx.update(&p_id, &value)?;
+                    audit::record(tx, "reprice", T::KIND, &p_id.to_string(), &request_id)?;
+                    let (min, max) = (&mut repriced.min_price_cents, &mut repriced.max_price_cents);
+                    *min = Some(min.map_or(price, |min| min.min(price)));
+                    *max = Some(max.map_or(price, |max| max.max(price)));
+                }
+                Ok(repriced)
+            })?;
+            tracing::info!(
+                "raised the prices of {} {} records by {}%",
+                repriced.updated,
+                T::KIND,
+                percent
+            );
+            Ok(AppJson(repriced))
+        })
+        .await
+    }
+    .await;
+    metrics::count_write(T::KIND, "reprice", &result);
+    result
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Payment {
+    pub amount_cents: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Receipt {
+    sale_id: String,
+    change_cents: u32,
+}
+
+// Sell one unit.  The price and stock checks, the decrement and the sale record share a
+// transaction so two concurrent purchases can't both take the last unit.  A sale leaving the
+// stock at or below `LOW_STOCK_THRESHOLD` logs a warning and counts a low-stock alert.  With
+// WEBHOOK_URL set the sale is also posted there, after the response is decided.
+async fn purchase<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(payment): AppJson<Payment>,
+) -> Result<AppJson<Receipt>, AppError> {
+    let p_id: structsy::Ref<T> = parse_ref(&id)?;
+    let webhook_state = state.clone();
+    let (receipt, event) = run_blocking(move || {
+        let sold = with_tx(state.db.of::<T>(), |tx| {
+            let Some(mut value) = live(tx.read(&p_id)?) else {
+                return Err(not_found::<T>(&id));
+            };
+            take_one(&id, &mut value)?;
+            sell(tx, &p_id, &id, value, &payment, &request_id)
+        })?;
+        Ok(sold.finish::<T>(&state, id, &payment))
+    })
+    .await?;
+    if let Some(webhook) = &webhook_state.webhook {
+        webhook.notify(event);
+    }
+    Ok(AppJson(receipt))
+}
+
+// Take a unit out of the stock, or 409 when there is none left.
+fn take_one<T: Beverage>(id: &str, value: &mut T) -> Result<(), AppError> {
+    if value.stock() == 0 {
+        return Err(AppError::OutOfStock(format!(
+            "{} {} is out of stock",
+            T::KIND,
+            id
+        )));
+    }
+    value.set_stock(value.stock() - 1);
+    Ok(())
+}
+
+// What a sale leaves to do once its transaction has committed.
+struct Sold {
+    sale_id: Ref<Sale>,
+    stock: u32,
+    price_cents: u32,
+    brand: String,
+}
+
+impl Sold {
+    // The low-stock alert, the receipt for the customer and the event for the webhook.
+    fn finish<T: Beverage>(
+        self,
+        state: &AppState,
+        id: String,
+        payment: &Payment,
+    ) -> (Receipt, SaleEvent) {
+        if self.stock <= state.config.low_stock_threshold {
+            tracing::warn!("{} {} is low on stock: {} left", T::KIND, id, self.stock);
+            metrics::count_low_stock(T::KIND);
+        }
+        let receipt = Receipt {
+            sale_id: self.sale_id.to_string(),
+            change_cents: payment.amount_cents - self.price_cents,
+        };
+        let event = SaleEvent {
+            kind: T::KIND,
+            id,
+            brand: self.brand,
+            price_cents: self.price_cents,
+        };
+        (receipt, event)
+    }
+}
+
+// Sell the unit already taken out of `value`'s stock: check the payment, count the purchase
+// and record the sale, all in `tx`.
+fn sell<T: Beverage>(
+    tx: &mut OwnedSytx,
+    p_id: &Ref<T>,
+    id: &str,
+    mut value: T,
+    payment: &Payment,
+    request_id: &RequestId,
+) -> Result<Sold, AppError> {
+    let price_cents = value.price_cents();
+    if payment.amount_cents < price_cents {
+        return Err(AppError::PaymentRequired(format!(
+            "{} {} costs {} cents, {} paid",
+            T::KIND,
+            id,
+            price_cents,
+            payment.amount_cents
+        )));
+    }
+    value.set_purchases(value.purchases() + 1);
+    value.set_version(value.version() + 1);
+    tx.update(p_id, &value)?;
+    audit::record(tx, "purchase", T::KIND, id, request_id)?;
+    let sale_id = tx.insert(&Sale::new(T::KIND, id, value.brand(), price_cents))?;
+    Ok(Sold {
+        sale_id,
+        stock: value.stock(),
+        price_cents,
+        brand: value.brand().to_owned(),
+    })
+}
+
+// Hold one unit for RESERVATION_TTL_SECS while the customer pays.  It leaves the stock now,
+// so nobody else can buy it meanwhile, and comes back if the reservation is cancelled or
+// expires unconfirmed.
+async fn reserve<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+) -> Result<(StatusCode, AppJson<Reserved>), AppError> {
+    let p_id: structsy::Ref<T> = parse_ref(&id)?;
+    run_blocking(move || {
+        let ttl = state.config.reservation_ttl;
+        let reserved = with_tx(state.db.of::<T>(), |tx| {
+            let Some(mut value) = live(tx.read(&p_id)?) else {
+                return Err(not_found::<T>(&id));
+            };
+            take_one(&id, &mut value)?;
+            value.set_version(value.version() + 1);
+            tx.update(&p_id, &value)?;
+            audit::record(tx, "reserve", T::KIND, &id, &request_id)?;
+            let reservation = Reservation::new(T::KIND, &id, ttl);
+            let expires_at = reservation.expires_at();
+            let r_id = tx.insert(&reservation)?;
+            Ok(Reserved {
+                id: r_id.to_string(),
+                ref_id: id.clone(),
+                expires_at,
+            })
+        })?;
+        Ok((StatusCode::CREATED, AppJson(reserved)))
+    })
+    .await
+}
+
+// Pay for a reserved unit: a purchase like `purchase`, of the unit the reservation holds.
+async fn confirm<T: Beverage>(
+    Path(r_id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(payment): AppJson<Payment>,
+) -> Result<AppJson<Receipt>, AppError> {
+    let webhook_state = state.clone();
+    let (receipt, event) = run_blocking(move || {
+        let (id, sold) = with_tx(state.db.of::<T>(), |tx| {
+            let (rid, reservation) = reservation::read::<T>(tx, &r_id)?;
+            if reservation.is_expired() {
+                return Err(AppError::NotFound(format!(
+                    "reservation {} has expired",
+                    r_id
+                )));
+            }
+            let id = reservation.ref_id().to_owned();
+            let p_id: Ref<T> = parse_ref(&id)?;
+            let Some(value) = live(tx.read(&p_id)?) else {
+                return Err(not_found::<T>(&id));
+            };
+            tx.delete(&rid)?;
+            let sold = sell(tx, &p_id, &id, value, &payment, &request_id)?;
+            Ok((id, sold))
+        })?;
+        Ok(sold.finish::<T>(&state, id, &payment))
+    })
+    .await?;
+    if let Some(webhook) = &webhook_state.webhook {
+        webhook.notify(event);
+    }
+    Ok(AppJson(receipt))
+}
+
+// Give up a reservation, putting its unit back into the stock.
+async fn cancel<T: Beverage>(
+    Path(r_id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+) -> Result<(), AppError> {
+    run_blocking(move || {
+        with_tx(state.db.of::<T>(), |tx| {
+            let (rid, reservation) = reservation::read::<T>(tx, &r_id)?;
+            reservation::release::<T>(tx, &rid, &reservation, &request_id)
+        })
+    })
+    .await
+}
+
+// `POST /<kind>/:id/restock` body.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct Restock {
+    quantity: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StockLevel {
+    stock: u32,
+}
+
+// A technician refilling the machine: add `quantity` units to the stock.  Like a purchase it
+// needs no version, the increment is applied to whatever is stored.
+async fn restock<T: Beverage>(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(Restock { quantity }): AppJson<Restock>,
+) -> Result<AppJson<StockLevel>, AppError> {
+    let result = async {
+        if quantity == 0 {
+            return Err(AppError::BadRequest(
+                "quantity must be greater than 0".to_owned(),
+            ));
+        }
+        let p_id: structsy::Ref<T> = parse_ref(&id)?;
+        run_blocking(move || {
+            let (before, after) = with_tx(state.db.of::<T>(), |tx| {
+                let Some(mut value) = live(tx.read(&p_id)?) else {
+                    return Err(not_found::<T>(&id));
+                };
+                let before = value.stock();
+                let after = before.checked_add(quantity).ok_or_else(|| {
+                    AppError::BadRequest(format!(
+                        "{} {} holds {} units, {} more is too many",
+                        T::KIND,
+                        id,
+                        before,
+                        quantity
+                    ))
+                })?;
+                value.set_stock(after);
+                value.set_version(value.version() + 1);
+                tx.update(&p_id, &value)?;
+                audit::record(tx, "restock", T::KIND, &id, &request_id)?;
+                Ok((before, after))
+            })?;
+            tracing::info!("restocked {} {}: {} -> {}", T::KIND, id, before, after);
+            Ok(AppJson(StockLevel { stock: after }))
+        })
+        .await
+    }
+    .await;
+    metrics::count_write(T::KIND, "restock", &result);
+    result
+}
+
+// The CRUD routes for one drink type, to be nested under `/<kind>`.  `openapi` documents them
+// and has to be kept in step.  Reads and purchases are
+// open; managing the records needs the API key.
+pub fn routes<T: Beverage>(state: AppState) -> Router {
+    let admin = Router::new()
+        .route("/create", post(create::<T>))
+        .route("/batch", post(create_batch::<T>))
+        .route("/batch-delete", post(delete_batch::<T>))
+        .route("/rename-brand", post(rename_brand::<T>))
+        .route("/prices", post(reprice::<T>))
+        .route("/by-brand/:brand", delete(delete_by_brand::<T>))
+        .route("/:id", patch(patch_one::<T>))
+        .route("/update/:id", post(update::<T>))
+        .route("/delete/:id", delete(remove::<T>))
+        .route("/:id/restore", post(restore::<T>))
+        .route("/:id/restock", post(restock::<T>))
+        .route_layer(from_fn_with_state(state.clone(), auth::require_api_key));
+    Router::new()
+        .route("/", get(list::<T>))
+        .route("/list", get(list::<T>))
+        .route("/search", post(search::<T>))
+        .route("/count", get(count::<T>))
+        .route("/stats", get(stats::<T>))
+        .route("/by-brand", get(by_brand::<T>))
+        .route("/brands", get(brands::<T>))
+        .route("/export.csv", get(export_csv::<T>))
+        .route("/stream", get(stream::<T>))
+        .route("/random", get(random::<T>))
+        .route("/trending", get(trending::<T>))
+        .route("/low-stock", get(low_stock::<T>))
+        .route("/:id", get(fetch::<T>))
+        .route("/:id/purchase", post(purchase::<T>))
+        .route("/:id/reserve", post(reserve::<T>))
+        .route("/reservations/:id/confirm", post(confirm::<T>))
+        .route("/reservations/:id/cancel", post(cancel::<T>))
+        .merge(admin)
+        .with_state(state)
+}
+
+// The OpenAPI description of `routes::<T>()`, nested under `/<kind>` the same way.
+pub fn openapi<T: Beverage>() -> utoipa::openapi::OpenApi {
+    let tag = T::PLURAL;
+    let mut paths = Paths::new();
+    let mut add = |route: &str, method: HttpMethod, operation: OperationBuilder| {
+        paths.add_path_operation(route, vec![method], operation.build());
+    };
+    let filtered = |summary: &str| {
+        operation(tag, summary)
+            .parameters(Some(query::<ListFilter>()))
+            .response("400", problem("A malformed filter"))
+    };
+    let one = |summary: &str| {
+        operation(tag, summary)
+            .parameter(id())
+            .response("400", problem("A malformed id"))
+            .response("404", problem("No such record, or it is deleted"))
+    };
+    let items = || array(schema::<Item<T>>());
+
+    let list = || {
+        filtered(&format!("List {}", T::PLURAL))
+            .parameters(Some(query::<Pagination>()))
+            .parameters(Some(query::<Sorting>()))
+            .parameters(Some(query::<Fields>()))
+            .parameter(header_param("Accept"))
+            .parameter(header_param("If-Modified-Since"))
+            .response(
+                "400",
+                problem("A malformed filter, or a limit over MAX_PAGE_SIZE"),
+            )
+            .response(
+                "200",
+                also(
+                    json("A page of records", schema::<List<T>>()),
+                    "text/csv",
+                    string(),
+                ),
+            )
+            .response(
+                "304",
+                empty("No matching record changed since `If-Modified-Since`"),
+            )
+            .response("406", problem("`Accept` names neither JSON nor CSV"))
+    };
+    add("/", HttpMethod::Get, list());
+    add("/list", HttpMethod::Get, list());
+    add(
+        "/search",
+        HttpMethod::Post,
+        operation(tag, &format!("Search {}", T::PLURAL))
+            .request_body(Some(json_body(schema::<SearchRequest>())))
+            .response("200", json("A page of records", schema::<List<T>>()))
+            .response(
+                "400",
+                problem("A malformed filter, or a limit over MAX_PAGE_SIZE"),
+            )
+            .response("422", problem("Not a search")),
+    );
+    add(
+        "/count",
+        HttpMethod::Get,
+        filtered("Count records").response("200", json("How many match", schema::<Count>())),
+    );
+    add(
+        "/stats",
+        HttpMethod::Get,
+        filtered("Sizes and brands").response("200", json("Totals", schema::<Stats>())),
+    );
+    add(
+        "/by-brand",
+        HttpMethod::Get,
+        filtered("Records per brand").response("200", json("Biggest first", schema::<ByBrand>())),
+    );
+    add(
+        "/brands",
+        HttpMethod::Get,
+        filtered("The distinct brands").response("200", json("Alphabetical", array(string()))),
+    );
+    add(
+        "/export.csv",
+        HttpMethod::Get,
+        filtered("Download as CSV").response(
+            "200",
+            ok("id,brand,machine_id,size,time rows", "text/csv", string()),
+        ),
+    );
+    add(
+        "/stream",
+        HttpMethod::Get,
+        filtered("Stream as NDJSON").response(
+            "200",
+            ok(
+                "One item per line",
+                "application/x-ndjson",
+                schema::<Item<T>>(),
+            ),
+        ),
+    );
+    add(
+        "/random",
+        HttpMethod::Get,
+        filtered("Pick one at random")
+            .response("200", json("A matching record", schema::<Item<T>>()))
+            .response("404", problem("Nothing matches")),
+    );
+    add(
+        "/trending",
+        HttpMethod::Get,
+        operation(tag, "Most purchased")
+            .parameters(Some(query::<TrendingParams>()))
+            .response("200", json("Most purchased first", items())),
+    );
+    add(
+        "/low-stock",
+        HttpMethod::Get,
+        operation(tag, "Running low")
+            .parameters(Some(query::<LowStockParams>()))
+            .response("200", json("Emptiest first", items())),
+    );
+    add(
+        "/{id}",
+        HttpMethod::Get,
+        one("Fetch one")
+            .parameters(Some(query::<ShowDeleted>()))
+            .parameters(Some(query::<Fields>()))
+            .parameter(header_param("If-None-Match"))
+            .response("200", json("The record", schema::<Item<T>>()))
+            .response("304", empty("Unchanged since the ETag in `If-None-Match`")),
+    );
+    add(
+        "/{id}/purchase",
+        HttpMethod::Post,
+        one("Buy one unit")
+            .request_body(Some(json_body(schema::<Payment>())))
+            .response("200", json("The sale", schema::<Receipt>()))
+            .response("402", problem("Not enough paid"))
+            .response("409", problem("Out of stock")),
+    );
+    add(
+        "/{id}/reserve",
+        HttpMethod::Post,
+        one("Hold one unit for a while before paying")
+            .response("201", json("The reservation", schema::<Reserved>()))
+            .response("409", problem("Out of stock")),
+    );
+    let reservation = |summary: &str| {
+        operation(tag, summary)
+            .parameter(id())
+            .response("400", problem("A malformed id"))
+            .response("404", problem("No such reservation, or it has expired"))
+    };
+    add(
+        "/reservations/{id}/confirm",
+        HttpMethod::Post,
+        reservation("Buy the reserved unit")
+            .request_body(Some(json_body(schema::<Payment>())))
+            .response("200", json("The sale", schema::<Receipt>()))
+            .response("402", problem("Not enough paid")),
+    );
+    add(
+        "/reservations/{id}/cancel",
+        HttpMethod::Post,
+        reservation("Put the reserved unit back into the stock")
+            .response("200", empty("Cancelled")),
+    );
+
+    add(
+        "/create",
+        HttpMethod::Post,
+        admin(operation(tag, "Create one"))
+            .parameters(Some(query::<DryRun>()))
+            .request_body(Some(json_body(schema::<T::Create>())))
+            .response(
+                "200",
+                json("With `dry_run`, what would be stored", schema::<T>()),
+            )
+            .response("201", json("The record as stored", schema::<Item<T>>()))
+            .response("409", problem("Same brand and time as a stored record"))
+            .response("422", problem("The record failed validation")),
+    );
+    add(
+        "/batch",
+        HttpMethod::Post,
+        admin(operation(tag, "Create several, all or none"))
+            .request_body(Some(json_body(array(schema::<T::Create>()))))
+            .response("201", json("The records as stored", items()))
+            .response("409", problem("Same brand and time as a stored record"))
+            .response("422", problem("A record failed validation")),
+    );
+    add(
+        "/batch-delete",
+        HttpMethod::Post,
+        admin(operation(tag, "Delete several"))
+            .request_body(Some(json_body(array(string()))))
+            .response("200", json("What was deleted", schema::<DeleteSummary>())),
+    );
+    add(
+        "/by-brand/{brand}",
+        HttpMethod::Delete,
+        admin(operation(tag, "Soft-delete a brand"))
+            .parameter(path_param("brand"))
+            .response(
+                "200",
+                json("How many records were deleted", schema::<Discontinued>()),
+            ),
+    );
+    add(
+        "/rename-brand",
+        HttpMethod::Post,
+        admin(operation(tag, "Rename a brand"))
+            .request_body(Some(json_body(schema::<RenameBrand>())))
+            .response("200", json("How many records changed", schema::<Renamed>()))
+            .response(
+                "409",
+                problem("A renamed record would duplicate a stored one"),
+            )
+            .response("422", problem("`to` is empty")),
+    );
+    add(
+        "/prices",
+        HttpMethod::Post,
+        admin(operation(tag, "Raise prices by a percentage"))
+            .request_body(Some(json_body(schema::<PriceChange>())))
+            .response("200", json("What changed", schema::<Repriced>()))
+            .response(
+                "422",
+                problem("A negative percent, or a price grown too big"),
+            ),
+    );
+    add(
+        "/{id}",
+        HttpMethod::Patch,
+        admin(one("Change some fields"))
+            .parameters(Some(query::<DryRun>()))
+            .request_body(Some(json_body(versioned_schema::<T::Patch>())))
+            .response(
+                "200",
+                json(
+                    "The record as stored, or would be with `dry_run`",
+                    schema::<Item<T>>(),
+                ),
+            )
+            .response("409", problem("The record has moved on from `version`"))
+            .response("422", problem("The result failed validation")),
+    );
+    add(
+        "/update/{id}",
+        HttpMethod::Post,
+        admin(one("Replace a record"))
+            .parameters(Some(query::<DryRun>()))
+            .request_body(Some(json_body(schema::<T>())))
+            .response(
+                "200",
+                also(
+                    empty("Updated; with `dry_run`, the record as it would be stored"),
+                    "application/json",
+                    schema::<Item<T>>(),
+                ),
+            )
+            .response("409", problem("The record has moved on from `version`"))
+            .response("422", problem("The record failed validation")),
+    );
+    add(
+        "/delete/{id}",
+        HttpMethod::Delete,
+        admin(one("Soft-delete a record")).response("200", empty("Deleted")),
+    );
+    add(
+        "/{id}/restore",
+        HttpMethod::Post,
+        admin(one("Undo a delete")).response("200", json("The record", schema::<Item<T>>())),
+    );
+    add(
+        "/{id}/restock",
+        HttpMethod::Post,
+        admin(one("Add stock"))
+            .request_body(Some(json_body(schema::<Restock>())))
+            .response("200", json("The new stock level", schema::<StockLevel>())),
+    );
+
+    let mut components = Components::new();
+    register::<List<T>>(&mut components);
+    register::<PageMeta>(&mut components);
+    register::<SearchRequest>(&mut components);
+    register::<T::Create>(&mut components);
+    register::<T::Patch>(&mut components);
+    register::<Count>(&mut components);
+    register::<Stats>(&mut components);
+    register::<ByBrand>(&mut components);
+    register::<Payment>(&mut components);
+    register::<Receipt>(&mut components);
+    register::<Reserved>(&mut components);
+    register::<DeleteSummary>(&mut components);
+    register::<Discontinued>(&mut components);
+    register::<RenameBrand>(&mut components);
+    register::<Renamed>(&mut components);
+    register::<PriceChange>(&mut components);
+    register::<Repriced>(&mut components);
+    register::<Restock>(&mut components);
+    register::<StockLevel>(&mut components);
+    OpenApiBuilder::new()
+        .paths(paths)
+        .components(Some(components))
+        .build()
+}
+
+// `/drinks`, the combined list across every drink type.
+pub fn drink_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/drinks", get(drinks))
+        .with_state(state)
+}
+
+// Layouts written before `time` became a `Timestamp`; only used to migrate old databases.
+pub mod legacy {
+    use structsy::{derive::Persistent, Persistent, SRes, Structsy, StructsyTx};
+
+    use crate::timestamp::Timestamp;
+
+    #[derive(Persistent)]
+    pub struct Coffee {
+        brand: String,
+        size: u32,
+        time: String,
+    }
+
+    #[derive(Persistent)]
+    pub struct Beer {
+        brand: String,
+        size: u32,
+        time: String,
+    }
+
+    impl From<Coffee> for super::Coffee {
+        fn from(old: Coffee) -> Self {
+            let time = Timestamp::parse_lenient(&old.time);
+            super::Coffee {
+                brand_key: super::brand_key(&old.brand),
+                brand: old.brand,
+                // unknown; must be set before the record can be updated
+                machine_id: String::new(),
+                size: old.size,
+                category: Some(super::SizeCategory::of(old.size)),
+                time,
+                stock: 0,
+                price_cents: 0,
+                version: super::FIRST_VERSION,
+                // the last write we know of is the creation
+                updated_at: time,
+                purchases: 0,
+                deleted_at: None,
+            }
+        }
+    }
+
+    impl From<Beer> for super::Beer {
+        fn from(old: Beer) -> Self {
+            let time = Timestamp::parse_lenient(&old.time);
+            super::Beer {
+                brand_key: super::brand_key(&old.brand),
+                brand: old.brand,
+                // unknown; must be set before the record can be updated
+                machine_id: String::new(),
+                size: old.size,
+                category: Some(super::SizeCategory::of(old.size)),
+                time,
+                stock: 0,
+                price_cents: 0,
+                version: super::FIRST_VERSION,
+                // the last write we know of is the creation
+                updated_at: time,
+                purchases: 0,
+                deleted_at: None,
+            }
+        }
+    }
+
+    // Open the database at `path`, first rewriting any records still in a legacy layout.
+    //
+    // Structsy refuses to migrate from a layout that is no longer the stored one, so the file
+    // is opened once to see which types still need it.
+    pub fn open(path: &str) -> SRes<Structsy> {
+        let (coffee, beer) = {
+            let db = Structsy::open(Structsy::config(path).create(true))?;
+            let defined: Vec<_> = db.list_defined()?.collect();
+            (
+                defined.contains(&Coffee::get_description()),
+                defined.contains(&Beer::get_description()),
+            )
+        };
+        if !coffee && !beer {
+            return Structsy::open(Structsy::config(path));
+        }
+        let prepare = Structsy::prepare_open(path)?;
+        if coffee {
+            tracing::info!("migrating legacy coffee records");
+            prepare.migrate::<Coffee, super::Coffee>()?;
+        }
+        if beer {
+            tracing::info!("migrating legacy beer records");
+            prepare.migrate::<Beer, super::Beer>()?;
+        }
+        let db = prepare.open()?;
+        if coffee {
+            build_indexes::<super::Coffee>(&db)?;
+        }
+        if beer {
+            build_indexes::<super::Beer>(&db)?;
+        }
+        Ok(db)
+    }
+
+    // Migration rewrites records in place without touching indexes, so declare the new
+    // layout's indexes and fill them from the migrated records.
+    fn build_indexes<T: Persistent>(db: &Structsy) -> SRes<()> {
+        let mut tx = db.begin()?;
+        T::declare(&mut tx)?;
+        for (id, value) in db.scan::<T>()? {
+            value.put_indexes(&mut tx, &id)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use axum::{
+    body::{self, Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+
+// Longest part of a body that is logged; the rest is only counted.
+const MAX_LOGGED_BYTES: usize = 2048;
+
+// With LOG_BODIES on, log the JSON bodies of requests and responses at debug level, next to
+// their request id, for chasing what a client really sent.  Bodies of other types, and
+// request bodies too big to be accepted or of unknown length, pass through without being
+// looked at, so streams and downloads are never buffered.  The layer is not installed at all
+// with the flag off.
+pub async fn log_bodies(State(body_limit): State<usize>, req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    let fits = content_length(req.headers()).is_some_and(|length| length <= body_limit);
+    let req = if is_json(req.headers()) && fits {
+        let (parts, body) = req.into_parts();
+        match body::to_bytes(body, body_limit).await {
+            Ok(bytes) => {
+                let body = shown(&bytes);
+                tracing::debug!(request_id, %body, "request body");
+                Request::from_parts(parts, Body::from(bytes))
+            }
+            // the client went away mid-body; nothing is left to hand on
+            Err(err) => {
+                tracing::debug!(request_id, "failed to read the request body -> {}", err);
+                Request::from_parts(parts, Body::empty())
+            }
+        }
+    } else {
+        req
+    };
+
+    let response = next.run(req).await;
+    if !is_json(response.headers()) {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => {
+            let (status, body) = (parts.status, shown(&bytes));
+            tracing::debug!(request_id, %status, %body, "response body");
+            Response::from_parts(parts, Body::from(bytes))
+        }
+        Err(err) => {
+            tracing::debug!(request_id, "failed to read the response body -> {}", err);
+            Response::from_parts(parts, Body::empty())
+        }
+    }
+}
+
+// `application/json`, `application/problem+json` and the like.
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|essence| {
+            let essence = essence.trim();
+            essence == "application/json" || essence.ends_with("+json")
+        })
+}
+
+fn content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+// The body as text, cut at `MAX_LOGGED_BYTES`.
+fn shown(bytes: &Bytes) -> String {
+    let cut = bytes.len().min(MAX_LOGGED_BYTES);
+    let mut shown = String::from_utf8_lossy(&bytes[..cut]).into_owned();
+    if cut < bytes.len() {
+        shown.push_str(&format!("... ({} bytes more)", bytes.len() - cut));
+    }
+    shown
+}
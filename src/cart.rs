@@ -0,0 +1,470 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use structsy::derive::{Persistent, PersistentEmbedded};
+use structsy::{OwnedSytx, Ref, StructsyTx};
+use utoipa::ToSchema;
+
+use crate::audit::{self, RequestId};
+use crate::beverage::{Beer, Beverage, Coffee, Payment, Soda};
+use crate::compact::{self, NewId, Remap};
+use crate::db::Databases;
+use crate::problem::Problem;
+use crate::sale::Sale;
+use crate::timestamp::Timestamp;
+use crate::webhook::SaleEvent;
+use crate::{metrics, parse_ref, run_blocking, with_tx, with_txs, AppError, AppJson, AppState};
+
+// Drinks a customer is gathering to pay for at once.  Nothing leaves the stock until the
+// checkout, so an item can still sell out in between.
+#[derive(Persistent)]
+pub struct Cart {
+    items: Vec<CartItem>,
+    created_at: Timestamp,
+}
+
+impl Remap for Cart {
+    fn remap(&mut self, new_id: NewId) {
+        for item in &mut self.items {
+            compact::remap(&item.kind, &mut item.id, new_id);
+        }
+    }
+}
+
+// One line of a cart: so many units of one drink.
+#[derive(PersistentEmbedded, Serialize, Deserialize, ToSchema, Clone)]
+#[serde(deny_unknown_fields)]
+struct CartItem {
+    // coffee, beer or soda
+    kind: String,
+    // the drink's id, as in `/<kind>/:id`
+    id: String,
+    quantity: u32,
+}
+
+// Most units of one drink a cart can hold.  Each unit is a sale and a webhook call of its own
+// at the checkout, so a line can't be allowed to grow without bound.
+const MAX_QUANTITY: u32 = 100;
+
+#[derive(Serialize, ToSchema)]
+struct CartView {
+    id: String,
+    items: Vec<CartItem>,
+    created_at: Timestamp,
+}
+
+impl CartView {
+    fn new(id: &Ref<Cart>, cart: Cart) -> Self {
+        CartView {
+            id: id.to_string(),
+            items: cart.items,
+            created_at: cart.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct CheckoutReceipt {
+    // one sale per unit, as for single purchases
+    sale_ids: Vec<String>,
+    total_cents: u32,
+    change_cents: u32,
+}
+
+fn read(tx: &mut OwnedSytx, id: &str) -> Result<(Ref<Cart>, Cart), AppError> {
+    let c_id: Ref<Cart> = parse_ref(id)?;
+    match tx.read(&c_id)? {
+        Some(cart) => Ok((c_id, cart)),
+        None => Err(AppError::NotFound(format!("cart {} not found", id))),
+    }
+}
+
+// 404 unless `id` is a stored, undeleted `T`.  Read from the `T` file, which with
+// DB_FILES=per-kind is not the cart's.
+fn check_live<T: Beverage>(db: &Databases, id: &str) -> Result<(), AppError> {
+    let p_id: Ref<T> = parse_ref(id)?;
+    match db.of::<T>().connection().read(&p_id)? {
+        Some(value) if !value.is_deleted() => Ok(()),
+        _ => Err(AppError::NotFound(format!("{} {} not found", T::KIND, id))),
+    }
+}
+
+// A line once checked out, for what is done after the commit.
+struct Sold {
+    kind: &'static str,
+    id: String,
+    brand: String,
+    price_cents: u32,
+    quantity: u32,
+    stock: u32,
+}
+
+// Take `item.quantity` units of a `T` out of the stock and record a sale for each, in `tx`.
+fn sell<T: Beverage>(
+    tx: &mut OwnedSytx,
+    item: &CartItem,
+    sale_ids: &mut Vec<String>,
+    request_id: &RequestId,
+) -> Result<Sold, AppError> {
+    let p_id: Ref<T> = parse_ref(&item.id)?;
+    let Some(mut value) = tx.read(&p_id)?.filter(|value| !value.is_deleted()) else {
+        return Err(AppError::NotFound(format!(
+            "{} {} not found",
+            T::KIND,
+            item.id
+        )));
+    };
+    if value.stock() < item.quantity {
+        return Err(AppError::OutOfStock(format!(
+            "{} {} has {} left, {} in the cart",
+            T::KIND,
+            item.id,
+            value.stock(),
+            item.quantity
+        )));
+    }
+    value.set_stock(value.stock() - item.quantity);
+    value.set_purchases(value.purchases() + u64::from(item.quantity));
+    value.set_version(value.version() + 1);
+    tx.update(&p_id, &value)?;
+    audit::record(tx, "purchase", T::KIND, &item.id, request_id)?;
+    let price_cents = value.price_cents();
+    for _ in 0..item.quantity {
+        let sale_id = tx.insert(&Sale::new(T::KIND, &item.id, value.brand(), price_cents))?;
+        sale_ids.push(sale_id.to_string());
+    }
+    Ok(Sold {
+        kind: T::KIND,
+        id: item.id.clone(),
+        brand: value.brand().to_owned(),
+        price_cents,
+        quantity: item.quantity,
+        stock: value.stock(),
+    })
+}
+
+fn unknown_kind(kind: &str) -> AppError {
+    AppError::Validation(vec![format!(
+        "kind must be one of {}, {}, {}; got {:?}",
+        Coffee::KIND,
+        Beer::KIND,
+        Soda::KIND,
+        kind
+    )])
+}
+
+// Start an empty cart.
+#[utoipa::path(
+    post,
+    path = "/cart",
+    tag = "cart",
+    responses((status = 201, description = "The new cart", body = CartView))
+)]
+async fn create(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, AppJson<CartView>), AppError> {
+    run_blocking(move || {
+        let cart = with_tx(state.db.shared(), |tx| {
+            let cart = Cart {
+                items: Vec::new(),
+                created_at: Timestamp::now(),
+            };
+            let id = tx.insert(&cart)?;
+            Ok(CartView::new(&id, cart))
+        })?;
+        Ok((StatusCode::CREATED, AppJson(cart)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/cart/{id}",
+    tag = "cart",
+    params(("id" = String, Path)),
+    responses(
+        (status = 200, description = "The cart", body = CartView),
+        (
+            status = 404,
+            description = "No such cart",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn fetch(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<AppJson<CartView>, AppError> {
+    run_blocking(move || {
+        let c_id: Ref<Cart> = parse_ref(&id)?;
+        match state.db.shared().connection().read(&c_id)? {
+            Some(cart) => Ok(AppJson(CartView::new(&c_id, cart))),
+            None => Err(AppError::NotFound(format!("cart {} not found", id))),
+        }
+    })
+    .await
+}
+
+// Put units of a drink in the cart; a drink already there gets the quantities added up, up to
+// `MAX_QUANTITY`.  The drink has to exist, but its stock is only checked at the checkout.
+#[utoipa::path(
+    post,
+    path = "/cart/{id}/items",
+    tag = "cart",
+    params(("id" = String, Path)),
+    request_body = CartItem,
+    responses(
+        (status = 200, description = "The cart with the item added", body = CartView),
+        (
+            status = 404,
+            description = "No such cart or drink",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 422,
+            description = "An unknown kind, or a quantity of zero or over 100 in all",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn add_item(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    AppJson(item): AppJson<CartItem>,
+) -> Result<AppJson<CartView>, AppError> {
+    if item.quantity == 0 {
+        return Err(AppError::Validation(vec![
+            "quantity must be at least 1".to_owned()
+        ]));
+    }
+    run_blocking(move || {
+        match item.kind.as_str() {
+            Coffee::KIND => check_live::<Coffee>(&state.db, &item.id)?,
+            Beer::KIND => check_live::<Beer>(&state.db, &item.id)?,
+            Soda::KIND => check_live::<Soda>(&state.db, &item.id)?,
+            kind => return Err(unknown_kind(kind)),
+        }
+        with_tx(state.db.shared(), |tx| {
+            let (c_id, mut cart) = read(tx, &id)?;
+            let line = cart
+                .items
+                .iter_mut()
+                .find(|line| line.kind == item.kind && line.id == item.id);
+            let quantity = match line {
+                Some(line) => {
+                    line.quantity = line.quantity.saturating_add(item.quantity);
+                    line.quantity
+                }
+                None => {
+                    cart.items.push(item.clone());
+                    item.quantity
+                }
+            };
+            if quantity > MAX_QUANTITY {
+                return Err(AppError::Validation(vec![format!(
+                    "quantity must be at most {} per drink, the cart would hold {}",
+                    MAX_QUANTITY, quantity
+                )]));
+            }
+            tx.update(&c_id, &cart)?;
+            Ok(AppJson(CartView::new(&c_id, cart)))
+        })
+    })
+    .await
+}
+
+// Take a drink out of the cart, every unit of it.
+#[utoipa::path(
+    delete,
+    path = "/cart/{id}/items/{item_id}",
+    tag = "cart",
+    params(("id" = String, Path), ("item_id" = String, Path, description = "The drink's id")),
+    responses(
+        (status = 200, description = "The cart without the item", body = CartView),
+        (
+            status = 404,
+            description = "No such cart, or the drink isn't in it",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn remove_item(
+    Path((id, item_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Result<AppJson<CartView>, AppError> {
+    run_blocking(move || {
+        with_tx(state.db.shared(), |tx| {
+            let (c_id, mut cart) = read(tx, &id)?;
+            let before = cart.items.len();
+            cart.items.retain(|line| line.id != item_id);
+            if cart.items.len() == before {
+                return Err(AppError::NotFound(format!(
+                    "{} is not in cart {}",
+                    item_id, id
+                )));
+            }
+            tx.update(&c_id, &cart)?;
+            Ok(AppJson(CartView::new(&c_id, cart)))
+        })
+    })
+    .await
+}
+
+// Buy everything in the cart at once.  Every line's stock check and decrement, the sales and
+// the emptying of the cart are committed together, see `buy`: a line out of stock or a short
+// payment leaves every drink as it was, and a second checkout of the cart finds it empty.
+// Low stock and the webhook are handled as for `purchase`.
+#[utoipa::path(
+    post,
+    path = "/cart/{id}/checkout",
+    tag = "cart",
+    params(("id" = String, Path)),
+    request_body = Payment,
+    responses(
+        (status = 200, description = "The sales and the total", body = CheckoutReceipt),
+        (
+            status = 400,
+            description = "The cart is empty",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 402,
+            description = "Not enough paid for the whole cart",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 404,
+            description = "No such cart, or a drink in it is gone",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 409,
+            description = "A drink has fewer units left than the cart holds",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn checkout(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppJson(payment): AppJson<Payment>,
+) -> Result<AppJson<CheckoutReceipt>, AppError> {
+    let webhook_state = state.clone();
+    let (receipt, sold) = run_blocking(move || {
+        let (receipt, sold) = buy(&state.db, &id, &payment, &request_id)?;
+        for line in &sold {
+            if line.stock <= state.config.low_stock_threshold {
+                tracing::warn!(
+                    "{} {} is low on stock: {} left",
+                    line.kind,
+                    line.id,
+                    line.stock
+                );
+                metrics::count_low_stock(line.kind);
+            }
+        }
+        Ok((receipt, sold))
+    })
+    .await?;
+    if let Some(webhook) = &webhook_state.webhook {
+        for line in sold {
+            for _ in 0..line.quantity {
+                webhook.notify(SaleEvent {
+                    kind: line.kind,
+                    id: line.id.clone(),
+                    brand: line.brand.clone(),
+                    price_cents: line.price_cents,
+                });
+            }
+        }
+    }
+    Ok(AppJson(receipt))
+}
+
+// Sell every line of cart `id` and empty it, all committed together.  Each file gets a
+// transaction of its own: with DB_FILES=per-kind a line is sold in its drink type's file and
+// the cart is emptied in DB_PATH.
+fn buy(
+    db: &Databases,
+    id: &str,
+    payment: &Payment,
+    request_id: &RequestId,
+) -> Result<(CheckoutReceipt, Vec<Sold>), AppError> {
+    let files: Vec<_> = db.files().iter().map(|file| &**file).collect();
+    with_txs(&files, |txs| {
+        // DB_PATH, where the carts are, is the first file
+        let (c_id, mut cart) = read(&mut txs[0], id)?;
+        if cart.items.is_empty() {
+            return Err(AppError::BadRequest(format!("cart {} is empty", id)));
+        }
+        let mut sale_ids = Vec::new();
+        let mut sold = Vec::with_capacity(cart.items.len());
+        for item in &cart.items {
+            sold.push(match item.kind.as_str() {
+                Coffee::KIND => sell::<Coffee>(
+                    &mut txs[db.position(Coffee::KIND)],
+                    item,
+                    &mut sale_ids,
+                    request_id,
+                )?,
+                Beer::KIND => sell::<Beer>(
+                    &mut txs[db.position(Beer::KIND)],
+                    item,
+                    &mut sale_ids,
+                    request_id,
+                )?,
+                Soda::KIND => sell::<Soda>(
+                    &mut txs[db.position(Soda::KIND)],
+                    item,
+                    &mut sale_ids,
+                    request_id,
+                )?,
+                kind => return Err(unknown_kind(kind)),
+            });
+        }
+        let total_cents: u64 = sold
+            .iter()
+            .map(|line| u64::from(line.price_cents) * u64::from(line.quantity))
+            .sum();
+        if u64::from(payment.amount_cents) < total_cents {
+            return Err(AppError::PaymentRequired(format!(
+                "cart {} costs {} cents, {} paid",
+                id, total_cents, payment.amount_cents
+            )));
+        }
+        // below the payment, so it fits
+        let total_cents = total_cents as u32;
+        cart.items.clear();
+        txs[0].update(&c_id, &cart)?;
+        let receipt = CheckoutReceipt {
+            sale_ids,
+            total_cents,
+            change_cents: payment.amount_cents - total_cents,
+        };
+        Ok((receipt, sold))
+    })
+}
+
+// Carts are for customers, so like `purchase` they need no API key.
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/cart", post(create))
+        .route("/cart/:id", get(fetch))
+        .route("/cart/:id/items", post(add_item))
+        .route("/cart/:id/items/:item_id", delete(remove_item))
+        .route("/cart/:id/checkout", post(checkout))
+        .with_state(state)
+}
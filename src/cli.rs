@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::audit::RequestId;
+use crate::config::{Config, LogFormat};
+use crate::db::Databases;
+use crate::export::{self, ImportMode};
+use crate::AppError;
+
+// Flags override the matching environment variables; everything else stays env-only.  The
+// `///` comments below are the `--help` text.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Address to listen on [env: BIND_ADDR]
+    #[arg(long, global = true)]
+    bind: Option<SocketAddr>,
+    /// Database file [env: DB_PATH]
+    #[arg(long, global = true)]
+    db_path: Option<String>,
+    /// pretty or json [env: LOG_FORMAT]
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default)
+    Serve,
+    /// Write every record as JSON, in the `GET /v1/export` format
+    Export {
+        /// File to write instead of stdout
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// Load a document written by `export`
+    Import {
+        file: PathBuf,
+        /// Delete every stored drink first instead of appending
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+impl Cli {
+    pub fn apply(&self, mut config: Config) -> Config {
+        if let Some(bind) = self.bind {
+            config.bind_addr = bind;
+        }
+        if let Some(db_path) = &self.db_path {
+            config.db_path = db_path.clone();
+        }
+        if let Some(log_format) = self.log_format {
+            config.log_format = log_format;
+        }
+        config
+    }
+}
+
+// The admin subcommands, run against the database directly with no server involved.
+pub fn run(command: Command, db: &Databases) -> Result<(), AppError> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Export { out } => {
+            let doc = export::dump(db)?;
+            let out: Box<dyn Write> = match out {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            write_json(out, &doc)
+        }
+        Command::Import { file, replace } => {
+            let reader = BufReader::new(File::open(&file)?);
+            // a document that doesn't parse is the caller's mistake, not a failed read
+            let doc = serde_json::from_reader(reader).map_err(|err| {
+                if err.is_io() {
+                    return AppError::IOError(err.into());
+                }
+                AppError::BadRequest(format!("{} is not an export: {}", file.display(), err))
+            })?;
+            let mode = if replace {
+                ImportMode::Replace
+            } else {
+                ImportMode::Append
+            };
+            let imported = export::load(db, doc, mode, &RequestId::default())?;
+            write_json(std::io::stdout().lock(), &imported)
+        }
+    }
+}
+
+fn write_json<W: Write, T: serde::Serialize>(mut out: W, value: &T) -> Result<(), AppError> {
+    serde_json::to_writer_pretty(&mut out, value).map_err(|err| AppError::IOError(err.into()))?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
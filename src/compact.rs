@@ -0,0 +1,159 @@
+// Rebuilding the database files to give back the space deleted records leave.  Persy reuses
+// freed pages for later writes but only hands back those at the end of a file, and structsy
+// has no compaction of its own, so this copies every record into a fresh file which then takes
+// the old one's place.  The copies get new ids, handed out from the start of the new file, so
+// an id a client kept from before can name another record afterwards; the references stored
+// in sales, audit entries, carts and the like are rewritten.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use structsy::{OwnedSytx, Persistent, Structsy, StructsyTx};
+
+use crate::audit::AuditEntry;
+use crate::beverage::{Beer, Beverage, Coffee, Soda};
+use crate::cart::Cart;
+use crate::db::{Databases, DbFile};
+use crate::idempotency::IdempotencyRecord;
+use crate::reservation::Reservation;
+use crate::sale::Sale;
+use crate::webhook::PendingWebhook;
+use crate::{open_db, AppError};
+
+// The new id of the `kind` drink with the given old id, or `None` when it wasn't copied,
+// having been deleted for good before.
+pub type NewId<'a> = &'a dyn Fn(&str, &str) -> Option<String>;
+
+// A record holding the id of a drink, to point at the drink's copy.
+pub trait Remap {
+    fn remap(&mut self, new_id: NewId);
+}
+
+// Point `id`, of a `kind` drink, at the drink's copy; a drink no longer stored keeps its id.
+pub fn remap(kind: &str, id: &mut String, new_id: NewId) {
+    if let Some(new) = new_id(kind, id) {
+        *id = new;
+    }
+}
+
+// Copy every file into a fresh one and serve each from its copy.  Run with writes paused:
+// one committed meanwhile would be left behind in the old file.  Nothing is replaced unless
+// every file was copied and moved.
+pub fn rebuild(db: &Databases) -> Result<(), AppError> {
+    let files = db.files();
+    let mut fresh = Vec::with_capacity(files.len());
+    for file in files {
+        let path = format!("{}.compacting", file.path);
+        remove_if_there(&path)?;
+        fresh.push((open_db(&path)?, path));
+    }
+    let copied = copy_all(db, &fresh);
+    let copied = copied.and_then(|()| put_in_place(files, &fresh));
+    if copied.is_err() {
+        for (_, path) in &fresh {
+            if let Err(err) = remove_if_there(path) {
+                tracing::error!("failed to remove {} -> {}", path, err);
+            }
+        }
+        return copied;
+    }
+    for (file, (connection, _)) in files.iter().zip(fresh) {
+        file.replace(connection);
+        if let Err(err) = std::fs::remove_file(aside(&file.path)) {
+            tracing::error!(
+                "failed to remove the file {} replaced -> {}",
+                file.path,
+                err
+            );
+        }
+    }
+    Ok(())
+}
+
+fn copy_all(db: &Databases, fresh: &[(Structsy, String)]) -> Result<(), AppError> {
+    let old: Vec<_> = db.files().iter().map(|file| file.connection()).collect();
+    // every file's drinks before the rest: a cart in DB_PATH names drinks in per-kind files
+    let mut ids = vec![HashMap::new(); old.len()];
+    for ((from, (to, _)), ids) in old.iter().zip(fresh).zip(&mut ids) {
+        let mut tx = to.begin()?;
+        copy::<Coffee>(from, &mut tx, ids)?;
+        copy::<Beer>(from, &mut tx, ids)?;
+        copy::<Soda>(from, &mut tx, ids)?;
+        tx.commit()?;
+    }
+    // carts and undelivered webhooks name drinks in whichever file their type is kept
+    let home = |kind: &str, id: &str| match kind {
+        Coffee::KIND | Beer::KIND | Soda::KIND => ids[db.position(kind)].get(id).cloned(),
+        _ => None,
+    };
+    for (index, (from, (to, _))) in old.iter().zip(fresh).enumerate() {
+        // the rest is written together with its drink, so into the drink's file
+        let same_file = |_: &str, id: &str| ids[index].get(id).cloned();
+        let mut tx = to.begin()?;
+        copy_remapped::<Sale>(from, &mut tx, &same_file)?;
+        copy_remapped::<AuditEntry>(from, &mut tx, &same_file)?;
+        copy_remapped::<IdempotencyRecord>(from, &mut tx, &same_file)?;
+        copy_remapped::<Reservation>(from, &mut tx, &same_file)?;
+        copy_remapped::<Cart>(from, &mut tx, &home)?;
+        copy_remapped::<PendingWebhook>(from, &mut tx, &home)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// Copy every `T`, soft-deleted ones included, noting each one's new id.
+fn copy<T: Persistent>(
+    from: &Structsy,
+    to: &mut OwnedSytx,
+    ids: &mut HashMap<String, String>,
+) -> Result<(), AppError> {
+    for (id, value) in from.scan::<T>()? {
+        let new = to.insert(&value)?;
+        ids.insert(id.to_string(), new.to_string());
+    }
+    Ok(())
+}
+
+fn copy_remapped<T: Persistent + Remap>(
+    from: &Structsy,
+    to: &mut OwnedSytx,
+    new_id: NewId,
+) -> Result<(), AppError> {
+    for (_, mut value) in from.scan::<T>()? {
+        value.remap(new_id);
+        to.insert(&value)?;
+    }
+    Ok(())
+}
+
+// Where an old file waits until every copy is in place.
+fn aside(path: &str) -> String {
+    format!("{}.before-compact", path)
+}
+
+// Move each copy to its file's path, the old file aside.  On a failure the files moved so far
+// are moved back.
+fn put_in_place(files: &[Arc<DbFile>], fresh: &[(Structsy, String)]) -> Result<(), AppError> {
+    for (moved, (file, (_, rebuilt))) in files.iter().zip(fresh).enumerate() {
+        let moving = std::fs::rename(&file.path, aside(&file.path));
+        let Err(err) = moving.and_then(|()| std::fs::rename(rebuilt, &file.path)) else {
+            continue;
+        };
+        for file in &files[..=moved] {
+            let path = aside(&file.path);
+            if std::path::Path::new(&path).exists() {
+                if let Err(err) = std::fs::rename(&path, &file.path) {
+                    tracing::error!("failed to move {} back -> {}", path, err);
+                }
+            }
+        }
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+fn remove_if_there(path: &str) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,239 @@
+use axum::http::HeaderValue;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const DEFAULT_BIND_ADDR: &str = "127.0.0.1:3000";
+const DEFAULT_DB_PATH: &str = "./track.db";
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_RATE_LIMIT_PER_SEC: f64 = 50.0;
+const DEFAULT_RATE_LIMIT_BURST: u32 = 100;
+const DEFAULT_BODY_LIMIT_BYTES: usize = 64 * 1024;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_LOW_STOCK_THRESHOLD: u32 = 3;
+const DEFAULT_RESERVATION_TTL_SECS: u64 = 120;
+const DEFAULT_BACKUP_DIR: &str = "./backups";
+const DEFAULT_READ_CONCURRENCY: usize = 256;
+const DEFAULT_WRITE_CONCURRENCY: usize = 32;
+const DEFAULT_PAGE_SIZE: usize = 20;
+const DEFAULT_MAX_PAGE_SIZE: usize = 100;
+
+// Runtime settings, read once from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    // BIND_ADDR
+    pub bind_addr: SocketAddr,
+    // DB_PATH: the database file, or `:memory:` for one that lives only as long as the process
+    pub db_path: String,
+    // DB_FILES: shared (the default) keeps everything in DB_PATH, per-kind gives each drink
+    // type a file of its own next to it; see `db` for the layout and how to switch
+    pub db_files: DbFiles,
+    // SHUTDOWN_TIMEOUT_SECS: how long to wait for in-flight requests on shutdown
+    pub shutdown_timeout: Duration,
+    // REQUEST_TIMEOUT_SECS: longest a request may take before it is answered with a 504
+    pub request_timeout: Duration,
+    // IDEMPOTENCY_TTL_SECS: how long an `Idempotency-Key` keeps returning the record it created
+    pub idempotency_ttl: Duration,
+    // ALLOWED_ORIGINS: comma-separated origins for CORS.  Empty allows any origin, which is
+    // only accepted while CORS_STRICT is off.
+    pub allowed_origins: Vec<HeaderValue>,
+    // RATE_LIMIT_PER_SEC: sustained requests per second allowed per client IP; 0 disables
+    // rate limiting
+    pub rate_limit: f64,
+    // RATE_LIMIT_BURST: requests a client may make at once before being throttled
+    pub rate_limit_burst: u32,
+    // BODY_LIMIT_BYTES: largest request body accepted
+    pub body_limit: usize,
+    // API_KEY: required in `X-API-Key` by the mutating routes.  The server won't start
+    // without one unless AUTH_DISABLED is set.
+    pub api_key: Option<String>,
+    // AUTH_DISABLED: with no API_KEY, let the guarded routes through; for local development
+    pub auth_disabled: bool,
+    // LOG_FORMAT: `pretty` (the default) or `json`, one object per line for log aggregators
+    pub log_format: LogFormat,
+    // LOG_BODIES: log JSON request and response bodies at debug level, see
+    // `body_log::log_bodies`; for debugging only, they may hold anything a client sends
+    pub log_bodies: bool,
+    // DEFAULT_PAGE_SIZE: records in a page of a list when the request gives no `limit`
+    pub default_page_size: usize,
+    // MAX_PAGE_SIZE: largest `limit` a list accepts; more is refused with a 400, see
+    // `beverage::Pagination`
+    pub max_page_size: usize,
+    // LOW_STOCK_THRESHOLD: a drink left with this many units or fewer needs restocking
+    pub low_stock_threshold: u32,
+    // RESERVATION_TTL_SECS: how long a reserved unit is held before it goes back into the stock
+    pub reservation_ttl: Duration,
+    // BACKUP_DIR: where `POST /v1/admin/backup` writes its copies; created when missing
+    pub backup_dir: String,
+    // DB_AUTO_RECOVER: off, fresh or backup; what to do when DB_PATH is damaged, see
+    // `recovery::open`
+    pub auto_recover: AutoRecover,
+    // SEED: load sample drinks on startup when the store has none; for development
+    pub seed: bool,
+    // OTEL_EXPORTER_OTLP_ENDPOINT: OpenTelemetry collector (OTLP over gRPC) to send traces
+    // to; unset exports nothing
+    pub otlp_endpoint: Option<String>,
+    // READ_CONCURRENCY: API reads (GET, HEAD, OPTIONS) allowed in flight at once before
+    // further ones get a 503; 0 means no limit
+    pub read_concurrency: usize,
+    // WRITE_CONCURRENCY: the same for every other method
+    pub write_concurrency: usize,
+    // WEBHOOK_URL: receives a POST for every sale; unset sends nothing
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected json or pretty".to_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AutoRecover {
+    // refuse to start
+    #[default]
+    Off,
+    // move the damaged file aside and start empty
+    Fresh,
+    // move it aside and start from the newest backup in BACKUP_DIR
+    Backup,
+}
+
+impl std::str::FromStr for AutoRecover {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(AutoRecover::Off),
+            "fresh" => Ok(AutoRecover::Fresh),
+            "backup" => Ok(AutoRecover::Backup),
+            _ => Err("expected off, fresh or backup".to_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DbFiles {
+    #[default]
+    Shared,
+    PerKind,
+}
+
+impl std::str::FromStr for DbFiles {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "shared" => Ok(DbFiles::Shared),
+            "per-kind" => Ok(DbFiles::PerKind),
+            _ => Err("expected shared or per-kind".to_owned()),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config, String> {
+        let bind_addr = var("BIND_ADDR", DEFAULT_BIND_ADDR);
+        let bind_addr = bind_addr
+            .parse()
+            .map_err(|err| format!("BIND_ADDR {:?} is not a socket address: {}", bind_addr, err))?;
+        let shutdown_timeout = parse_var("SHUTDOWN_TIMEOUT_SECS", DEFAULT_SHUTDOWN_TIMEOUT_SECS)?;
+        let request_timeout = parse_var("REQUEST_TIMEOUT_SECS", DEFAULT_REQUEST_TIMEOUT_SECS)?;
+        let idempotency_ttl = parse_var("IDEMPOTENCY_TTL_SECS", DEFAULT_IDEMPOTENCY_TTL_SECS)?;
+        let allowed_origins = origins(&var("ALLOWED_ORIGINS", ""))?;
+        if parse_var("CORS_STRICT", false)? && allowed_origins.is_empty() {
+            return Err("CORS_STRICT is set but ALLOWED_ORIGINS is empty".to_owned());
+        }
+        let rate_limit = parse_var("RATE_LIMIT_PER_SEC", DEFAULT_RATE_LIMIT_PER_SEC)?;
+        if !(rate_limit >= 0.0 && rate_limit.is_finite()) {
+            return Err(format!(
+                "RATE_LIMIT_PER_SEC {} must be 0 or more",
+                rate_limit
+            ));
+        }
+        let default_page_size = parse_var("DEFAULT_PAGE_SIZE", DEFAULT_PAGE_SIZE)?;
+        let max_page_size = parse_var("MAX_PAGE_SIZE", DEFAULT_MAX_PAGE_SIZE)?;
+        if default_page_size == 0 || default_page_size > max_page_size {
+            return Err(format!(
+                "DEFAULT_PAGE_SIZE {} must be between 1 and MAX_PAGE_SIZE {}",
+                default_page_size, max_page_size
+            ));
+        }
+        Ok(Config {
+            bind_addr,
+            db_path: var("DB_PATH", DEFAULT_DB_PATH),
+            shutdown_timeout: Duration::from_secs(shutdown_timeout),
+            request_timeout: Duration::from_secs(request_timeout),
+            idempotency_ttl: Duration::from_secs(idempotency_ttl),
+            allowed_origins,
+            rate_limit,
+            rate_limit_burst: parse_var("RATE_LIMIT_BURST", DEFAULT_RATE_LIMIT_BURST)?,
+            body_limit: parse_var("BODY_LIMIT_BYTES", DEFAULT_BODY_LIMIT_BYTES)?,
+            api_key: std::env::var("API_KEY").ok().filter(|key| !key.is_empty()),
+            auth_disabled: parse_var("AUTH_DISABLED", false)?,
+            log_format: parse_var("LOG_FORMAT", LogFormat::default())?,
+            log_bodies: parse_var("LOG_BODIES", false)?,
+            default_page_size,
+            max_page_size,
+            low_stock_threshold: parse_var("LOW_STOCK_THRESHOLD", DEFAULT_LOW_STOCK_THRESHOLD)?,
+            reservation_ttl: Duration::from_secs(parse_var(
+                "RESERVATION_TTL_SECS",
+                DEFAULT_RESERVATION_TTL_SECS,
+            )?),
+            backup_dir: var("BACKUP_DIR", DEFAULT_BACKUP_DIR),
+            auto_recover: parse_var("DB_AUTO_RECOVER", AutoRecover::default())?,
+            db_files: parse_var("DB_FILES", DbFiles::default())?,
+            seed: parse_var("SEED", false)?,
+            read_concurrency: parse_var("READ_CONCURRENCY", DEFAULT_READ_CONCURRENCY)?,
+            write_concurrency: parse_var("WRITE_CONCURRENCY", DEFAULT_WRITE_CONCURRENCY)?,
+            webhook_url: std::env::var("WEBHOOK_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty()),
+            otlp_endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|endpoint| !endpoint.trim().is_empty()),
+        })
+    }
+}
+
+fn var(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_owned())
+}
+
+fn parse_var<T>(name: &str, default: T) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|err| format!("{} {:?} is invalid: {}", name, value, err)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn origins(value: &str) -> Result<Vec<HeaderValue>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            HeaderValue::from_str(origin)
+                .map_err(|_| format!("ALLOWED_ORIGINS entry {:?} is not a valid origin", origin))
+        })
+        .collect()
+}
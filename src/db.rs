@@ -0,0 +1,149 @@
+// Where the records live.  By default (DB_FILES=shared) everything is in the one DB_PATH file.
+// With DB_FILES=per-kind each drink type gets a file of its own next to it, `track.db` giving
+// `track-coffee.db`, `track-beer.db` and `track-soda.db`, which can be backed up and compacted
+// apart.  A type's file also holds what is written in the same transactions as its records,
+// that is their sales, audit entries, idempotency keys and reservations, so nearly every write
+// stays in one transaction; carts and undelivered webhooks stay in DB_PATH, and a checkout
+// commits to several files at once with `with_txs`.  The reports that read several types
+// (`/drinks`, `/audit`, `/reports/daily`, `/export`) read every file.
+//
+// Moving an existing store to per-kind files: with the server stopped, write it out with the
+// `export` command, start once with DB_FILES=per-kind so the new files are created, then
+// `import` the document.  Records get new ids on import, as with any import.  Sales and audit
+// entries stay in DB_PATH, where the reports still find them; the drinks left there are no
+// longer read.  Going back is the same with the two settings swapped.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use structsy::Structsy;
+
+use crate::beverage::{Beer, Beverage, Coffee, Soda};
+use crate::config::{Config, DbFiles};
+use crate::{recovery, MEMORY_DB};
+
+// One database file and its connection.
+pub struct DbFile {
+    // as opened, or `MEMORY_DB`
+    pub path: String,
+    // swapped for the rebuilt file's by `POST /admin/compact?rebuild=true`
+    connection: RwLock<Structsy>,
+}
+
+impl DbFile {
+    fn new(path: &str, connection: Structsy) -> Arc<Self> {
+        Arc::new(DbFile {
+            path: path.to_owned(),
+            connection: RwLock::new(connection),
+        })
+    }
+
+    // The connection as of now.  A write has to take it in its turn, as `with_tx` does, or it
+    // could commit to a file a compaction has just replaced.
+    pub fn connection(&self) -> Structsy {
+        self.connection
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // Serve the file from `connection` from now on.  Only with writes paused.
+    pub fn replace(&self, connection: Structsy) {
+        *self
+            .connection
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = connection;
+    }
+}
+
+pub struct Databases {
+    // DB_PATH first, then the per-kind files if any; shared with the webhook, which keeps the
+    // undelivered events in DB_PATH
+    files: Vec<Arc<DbFile>>,
+    // per drink type, its file in `files`
+    kinds: BTreeMap<&'static str, usize>,
+}
+
+impl Databases {
+    // Open, recovering if need be, every file DB_FILES calls for.
+    pub fn open(config: &Config) -> Result<Self, String> {
+        let mut databases = Databases {
+            files: vec![open_file(config, &config.db_path)?],
+            kinds: BTreeMap::new(),
+        };
+        for kind in [Coffee::KIND, Beer::KIND, Soda::KIND] {
+            let index = match config.db_files {
+                DbFiles::Shared => 0,
+                DbFiles::PerKind => {
+                    let path = kind_path(&config.db_path, kind);
+                    databases.files.push(open_file(config, &path)?);
+                    databases.files.len() - 1
+                }
+            };
+            databases.kinds.insert(kind, index);
+        }
+        Ok(databases)
+    }
+
+    // Everything in one file opened already, like DB_FILES=shared; `path` only names it.
+    pub fn single(path: &str, connection: Structsy) -> Self {
+        Databases {
+            files: vec![DbFile::new(path, connection)],
+            kinds: [Coffee::KIND, Beer::KIND, Soda::KIND]
+                .into_iter()
+                .map(|kind| (kind, 0))
+                .collect(),
+        }
+    }
+
+    // DB_PATH, with the carts and undelivered webhooks.
+    pub fn shared(&self) -> &Arc<DbFile> {
+        &self.files[0]
+    }
+
+    // The file with the `T` records.
+    pub fn of<T: Beverage>(&self) -> &DbFile {
+        self.file(T::KIND)
+    }
+
+    // The file of drink type `kind`; the type has to be one of the three.
+    pub fn file(&self, kind: &str) -> &DbFile {
+        &self.files[self.position(kind)]
+    }
+
+    // Where the file of `kind` is in `files`.
+    pub fn position(&self, kind: &str) -> usize {
+        self.kinds[kind]
+    }
+
+    // Every file once, DB_PATH first.
+    pub fn files(&self) -> &[Arc<DbFile>] {
+        &self.files
+    }
+
+    // Whether drink types have files of their own, where one transaction can't span types.
+    pub fn partitioned(&self) -> bool {
+        self.files.len() > 1
+    }
+}
+
+fn open_file(config: &Config, path: &str) -> Result<Arc<DbFile>, String> {
+    Ok(DbFile::new(path, recovery::open(config, path)?))
+}
+
+// `track.db` becomes `track-coffee.db`, in the same directory.  An in-memory store gets another
+// in-memory store.
+fn kind_path(db_path: &str, kind: &str) -> String {
+    if db_path == MEMORY_DB {
+        return MEMORY_DB.to_owned();
+    }
+    let path = Path::new(db_path);
+    let stem = path
+        .file_stem()
+        .map_or("db".into(), |stem| stem.to_string_lossy());
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, kind, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, kind),
+    };
+    path.with_file_name(name).display().to_string()
+}
@@ -0,0 +1,86 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use futures_util::{Stream, StreamExt};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+use crate::beverage::{Beverage, Item};
+use crate::AppState;
+
+// Events held for a subscriber that reads slower than drinks are created; past this it skips
+// ahead and is told how many it missed.
+const CAPACITY: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// One message for the subscribers: the SSE event name and its JSON data.
+#[derive(Clone)]
+struct Message {
+    name: &'static str,
+    data: String,
+}
+
+// Fan-out of changes to the `/events` subscribers.  Publishing with nobody subscribed is a
+// no-op.
+pub struct Events(broadcast::Sender<Message>);
+
+impl Events {
+    pub fn new() -> Self {
+        Events(broadcast::channel(CAPACITY).0)
+    }
+
+    // A drink was stored; sent shaped like a `/drinks` item: `{"id", "kind", "<kind>"}`.
+    pub fn created<T: Beverage>(&self, item: &Item<T>) {
+        if self.0.receiver_count() == 0 {
+            return;
+        }
+        let Ok(mut data) = serde_json::to_value(item) else {
+            return;
+        };
+        data["kind"] = T::KIND.into();
+        let _ = self.0.send(Message {
+            name: "created",
+            data: data.to_string(),
+        });
+    }
+}
+
+// Every drink created from now on, as a `created` event.  A subscriber that falls too far
+// behind gets a `lagged` event with the number of events it lost.  The receiver goes away
+// with the connection, and the stream ends on shutdown so it doesn't hold up the drain.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "drinks",
+    responses((
+        status = 200,
+        description = "A server-sent event per drink created",
+        content_type = "text/event-stream",
+    ))
+)]
+async fn events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.events.0.subscribe()).map(|message| {
+        Ok(match message {
+            Ok(message) => Event::default().event(message.name).data(message.data),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                Event::default().event("lagged").data(missed.to_string())
+            }
+        })
+    });
+    let shutdown = state.shutdown.started();
+    Sse::new(stream.take_until(shutdown)).keep_alive(KeepAlive::new().interval(KEEP_ALIVE))
+}
+
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/events", get(events))
+        .with_state(state)
+}
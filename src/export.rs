@@ -0,0 +1,230 @@
+use axum::{
+    extract::State,
+    middleware::from_fn_with_state,
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use structsy::{OwnedSytx, Snapshot, Structsy, StructsyTx};
+use utoipa::openapi::{schema::Schema, RefOr};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::audit::{self, RequestId};
+use crate::beverage::{check_unique, Beer, Beverage, Coffee, Item, Soda, FIRST_VERSION};
+use crate::db::Databases;
+use crate::openapi;
+use crate::problem::Problem;
+use crate::{auth, run_blocking, with_tx, AppError, AppJson, AppQuery, AppState};
+
+// The whole store as one JSON document, independent of the structsy file format.  Every list
+// uses the same item shape as the API, so ids are included.  On import a missing list counts
+// as empty.
+#[derive(Serialize, Deserialize, Default, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Export {
+    #[schema(schema_with = item_list::<Coffee>)]
+    coffees: Vec<Item<Coffee>>,
+    #[schema(schema_with = item_list::<Beer>)]
+    beers: Vec<Item<Beer>>,
+    #[schema(schema_with = item_list::<Soda>)]
+    sodas: Vec<Item<Soda>>,
+}
+
+fn item_list<T: Beverage>() -> RefOr<Schema> {
+    openapi::array(openapi::schema::<Item<T>>())
+}
+
+fn items<T: Beverage>(snapshot: &Snapshot) -> Result<Vec<Item<T>>, AppError> {
+    Ok(snapshot
+        .scan::<T>()?
+        .map(|(id, value)| Item {
+            id: id.to_string(),
+            value,
+        })
+        .collect())
+}
+
+// Each file is read from one snapshot, so the export is consistent even under writes; with
+// every type in one file the types are consistent with each other too.
+pub fn dump(db: &Databases) -> Result<Export, AppError> {
+    let snapshots = db
+        .files()
+        .iter()
+        .map(|file| file.connection().snapshot())
+        .collect::<Result<Vec<_>, _>>()?;
+    let snapshot = |kind| &snapshots[db.position(kind)];
+    Ok(Export {
+        coffees: items(snapshot(Coffee::KIND))?,
+        beers: items(snapshot(Beer::KIND))?,
+        sodas: items(snapshot(Soda::KIND))?,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "export",
+    responses((status = 200, description = "Every record", body = Export))
+)]
+async fn export(State(state): State<AppState>) -> Result<AppJson<Export>, AppError> {
+    run_blocking(move || dump(&state.db).map(AppJson)).await
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    // Delete every stored drink before inserting
+    Replace,
+    // Insert on top of what is already stored
+    #[default]
+    Append,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportParams {
+    #[serde(default)]
+    #[param(inline)]
+    mode: ImportMode,
+}
+
+// Number of records inserted per type.
+#[derive(Serialize, ToSchema)]
+pub struct Imported {
+    coffees: usize,
+    beers: usize,
+    sodas: usize,
+}
+
+// Check every record up front and report the problems as `<plural>[<index>] <problem>`.
+fn validate_all<T: Beverage>(items: &[Item<T>], problems: &mut Vec<String>) {
+    for (index, item) in items.iter().enumerate() {
+        if let Err(errs) = item.value.validate() {
+            for e in errs {
+                problems.push(format!("{}[{}] {}", T::PLURAL, index, e));
+            }
+        }
+    }
+}
+
+fn restore<T: Beverage>(
+    connection: &Structsy,
+    tx: &mut OwnedSytx,
+    items: &mut [Item<T>],
+    mode: ImportMode,
+    request_id: &RequestId,
+) -> Result<usize, AppError> {
+    if mode == ImportMode::Replace {
+        for (id, _) in connection.scan::<T>()? {
+            tx.delete(&id)?;
+            audit::record(tx, "delete", T::KIND, &id.to_string(), request_id)?;
+        }
+    }
+    let count = items.len();
+    for item in items.iter_mut() {
+        item.value.set_version(FIRST_VERSION);
+        item.value.refresh_derived();
+        check_unique(tx, &item.value)?;
+        let id = tx.insert(&item.value)?;
+        audit::record(tx, "import", T::KIND, &id.to_string(), request_id)?;
+    }
+    Ok(count)
+}
+
+// Load a document produced by `dump` in one transaction, or with DB_FILES=per-kind in one per
+// type: there a duplicate found in a later type leaves the earlier ones imported.  Records get
+// fresh ids; the exported ones are accepted but not reused, and versions start over.  Times
+// are kept as exported.
+pub fn load(
+    db: &Databases,
+    mut doc: Export,
+    mode: ImportMode,
+    request_id: &RequestId,
+) -> Result<Imported, AppError> {
+    let mut problems = Vec::new();
+    validate_all(&doc.coffees, &mut problems);
+    validate_all(&doc.beers, &mut problems);
+    validate_all(&doc.sodas, &mut problems);
+    if !problems.is_empty() {
+        return Err(AppError::Validation(problems));
+    }
+    if !db.partitioned() {
+        let file = db.shared();
+        return with_tx(file, |tx| {
+            let connection = &file.connection();
+            Ok(Imported {
+                coffees: restore(connection, tx, &mut doc.coffees, mode, request_id)?,
+                beers: restore(connection, tx, &mut doc.beers, mode, request_id)?,
+                sodas: restore(connection, tx, &mut doc.sodas, mode, request_id)?,
+            })
+        });
+    }
+    let (coffees, beers, sodas) = (db.of::<Coffee>(), db.of::<Beer>(), db.of::<Soda>());
+    Ok(Imported {
+        coffees: with_tx(coffees, |tx| {
+            restore(
+                &coffees.connection(),
+                tx,
+                &mut doc.coffees,
+                mode,
+                request_id,
+            )
+        })?,
+        beers: with_tx(beers, |tx| {
+            restore(&beers.connection(), tx, &mut doc.beers, mode, request_id)
+        })?,
+        sodas: with_tx(sodas, |tx| {
+            restore(&sodas.connection(), tx, &mut doc.sodas, mode, request_id)
+        })?,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/import",
+    tag = "export",
+    params(ImportParams),
+    request_body = Export,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Records inserted per type", body = Imported),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 409,
+            description = "A record duplicates a stored one",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 422,
+            description = "The document failed validation",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn import(
+    State(state): State<AppState>,
+    request_id: RequestId,
+    AppQuery(params): AppQuery<ImportParams>,
+    AppJson(doc): AppJson<Export>,
+) -> Result<AppJson<Imported>, AppError> {
+    run_blocking(move || load(&state.db, doc, params.mode, &request_id).map(AppJson)).await
+}
+
+// Importing rewrites the store, so it needs the API key like the other mutations.
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/export", get(export))
+        .merge(
+            Router::new()
+                .route("/import", post(import))
+                .route_layer(from_fn_with_state(state.clone(), auth::require_api_key)),
+        )
+        .with_state(state)
+}
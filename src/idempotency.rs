@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use structsy::{
+    derive::{queries, Persistent},
+    OwnedSytx, Ref, SRes, StructsyTx,
+};
+
+use crate::compact::{self, NewId, Remap};
+use crate::timestamp::Timestamp;
+
+// Which record a create carrying an `Idempotency-Key` produced, so a retry of the same request
+// gets that record back instead of inserting a duplicate.
+#[derive(Persistent)]
+pub struct IdempotencyRecord {
+    // `<kind>:<key>`, so each drink type has its own key space
+    #[index(mode = "exclusive")]
+    key: String,
+    ref_id: String,
+    created: Timestamp,
+}
+
+#[queries(IdempotencyRecord)]
+trait IdempotencyQuery {
+    fn by_key(self, key: String) -> Self;
+}
+
+fn scoped(kind: &str, key: &str) -> String {
+    format!("{}:{}", kind, key)
+}
+
+impl IdempotencyRecord {
+    pub fn new(kind: &str, key: &str, ref_id: &str) -> Self {
+        IdempotencyRecord {
+            key: scoped(kind, key),
+            ref_id: ref_id.to_owned(),
+            created: Timestamp::now(),
+        }
+    }
+}
+
+impl Remap for IdempotencyRecord {
+    fn remap(&mut self, new_id: NewId) {
+        let kind = self.key.split(':').next().unwrap_or_default();
+        compact::remap(kind, &mut self.ref_id, new_id);
+    }
+}
+
+// The `Idempotency-Key` header, if the client sent a usable one.
+pub fn key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("idempotency-key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_owned)
+}
+
+// The id an earlier create with `key` produced, along with the record remembering it.  Keys
+// older than `ttl` are deleted on the way and count as unused.
+pub fn lookup(
+    tx: &mut OwnedSytx,
+    kind: &str,
+    key: &str,
+    ttl: Duration,
+) -> SRes<Option<(Ref<IdempotencyRecord>, String)>> {
+    let found: Vec<_> = tx
+        .query::<IdempotencyRecord>()
+        .by_key(scoped(kind, key))
+        .fetch()
+        .collect();
+    for (id, record) in found {
+        if record.created.older_than(ttl) {
+            tx.delete(&id)?;
+        } else {
+            return Ok(Some((id, record.ref_id)));
+        }
+    }
+    Ok(None)
+}
+
+// Delete every key older than `ttl`.  `lookup` only drops the expired keys it comes across,
+// so those never sent again would otherwise stay stored.  Returns how many went.
+pub fn purge_expired(tx: &mut OwnedSytx, ttl: Duration) -> SRes<usize> {
+    let expired: Vec<_> = tx
+        .scan::<IdempotencyRecord>()?
+        .filter(|(_, record)| record.created.older_than(ttl))
+        .map(|(id, _)| id)
+        .collect();
+    for id in &expired {
+        tx.delete(id)?;
+    }
+    Ok(expired.len())
+}
@@ -0,0 +1,1137 @@
+// The server as a library, so the tests under `tests/` can build it; `main.rs` only calls `run`.
+mod admin;
+mod audit;
+mod auth;
+pub mod beverage;
+mod body_log;
+mod cart;
+mod cli;
+mod compact;
+pub mod config;
+pub mod db;
+mod events;
+mod export;
+mod idempotency;
+mod load_shed;
+mod maintenance;
+mod metrics;
+mod openapi;
+mod problem;
+mod rate_limit;
+mod recovery;
+pub mod reservation;
+mod sale;
+mod seed;
+mod size;
+mod status;
+mod telemetry;
+mod timestamp;
+mod webhook;
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        DefaultBodyLimit, FromRequest, FromRequestParts, Request, State,
+    },
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tower::Layer as _;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate, SizeAbove},
+    CompressionLayer,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::normalize_path::NormalizePathLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{error_span, field};
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, Layer,
+};
+
+use metrics_exporter_prometheus::PrometheusHandle;
+use persy::PersyError;
+use serde::Serialize;
+use std::any::Any;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use structsy::{OwnedSytx, Persistent, Structsy, StructsyError, StructsyTx};
+use tokio::sync::{watch, Notify};
+use utoipa::ToSchema;
+
+use beverage::{Beer, Coffee, Soda};
+use clap::Parser;
+use config::{Config, LogFormat};
+use problem::Problem;
+use sale::Sale;
+
+// Responses smaller than this are sent uncompressed.
+const MIN_COMPRESS_BYTES: u16 = 1024;
+
+#[derive(Debug)]
+pub enum AppError {
+    // The request body contained invalid JSON
+    JsonRejection(JsonRejection),
+    // The query string could not be deserialized
+    QueryRejection(QueryRejection),
+    StructsyError(StructsyError), // Database error
+    IOError(std::io::Error),
+    // A blocking database task panicked or was cancelled
+    JoinError(tokio::task::JoinError),
+    // A handler panicked; holds the panic message
+    Panic(String),
+    // The request took longer than the configured timeout
+    Timeout(Duration),
+    // The requested record does not exist
+    NotFound(String),
+    // The route exists but not for this method
+    MethodNotAllowed,
+    // The id in the path could not be parsed into a record reference
+    BadRef(String),
+    // A write was based on a stale version of the record
+    Conflict(String),
+    // A purchase was attempted on a drink with no stock left
+    OutOfStock(String),
+    // The payment did not cover the price
+    PaymentRequired(String),
+    // The request body deserialized but failed validation
+    Validation(Vec<String>),
+    // The client went over its request rate; it may retry after the given delay
+    RateLimited(Duration),
+    // A mutating route was called without a valid API key
+    Unauthorized(String),
+    // The query parameters parsed but don't make sense together
+    BadRequest(String),
+    // A JSON body was sent without a JSON content type
+    UnsupportedMediaType,
+    // Maintenance mode is on; holds the seconds to send in `Retry-After`
+    Maintenance(u64),
+    // The `Accept` header names no representation the route can produce
+    NotAcceptable(String),
+    // Too many requests of the kind are in flight; holds the seconds for `Retry-After`
+    Overloaded(u64),
+}
+
+impl AppError {
+    // Every `code`, for the API document.  Codes are part of the API: one never changes
+    // meaning or goes away, and a new kind of error gets a new one.
+    pub const CODES: &'static [&'static str] = &[
+        "INVALID_BODY",
+        "INVALID_QUERY",
+        "INTERNAL",
+        "TIMEOUT",
+        "NOT_FOUND",
+        "METHOD_NOT_ALLOWED",
+        "INVALID_ID",
+        "CONFLICT",
+        "OUT_OF_STOCK",
+        "PAYMENT_REQUIRED",
+        "VALIDATION",
+        "RATE_LIMITED",
+        "UNAUTHORIZED",
+        "BAD_REQUEST",
+        "UNSUPPORTED_MEDIA_TYPE",
+        "MAINTENANCE",
+        "NOT_ACCEPTABLE",
+        "OVERLOADED",
+    ];
+
+    // The machine-readable `code` of the problem document, for clients to branch on instead
+    // of the wording of `detail`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::JsonRejection(_) => "INVALID_BODY",
+            AppError::QueryRejection(_) => "INVALID_QUERY",
+            // what went wrong inside is for the logs, not for clients to tell apart
+            AppError::StructsyError(_)
+            | AppError::IOError(_)
+            | AppError::JoinError(_)
+            | AppError::Panic(_) => "INTERNAL",
+            AppError::Timeout(_) => "TIMEOUT",
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            AppError::BadRef(_) => "INVALID_ID",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::OutOfStock(_) => "OUT_OF_STOCK",
+            AppError::PaymentRequired(_) => "PAYMENT_REQUIRED",
+            AppError::Validation(_) => "VALIDATION",
+            AppError::RateLimited(_) => "RATE_LIMITED",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::BadRequest(_) => "BAD_REQUEST",
+            AppError::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            AppError::Maintenance(_) => "MAINTENANCE",
+            AppError::NotAcceptable(_) => "NOT_ACCEPTABLE",
+            AppError::Overloaded(_) => "OVERLOADED",
+        }
+    }
+}
+
+impl From<StructsyError> for AppError {
+    fn from(e: structsy::StructsyError) -> Self {
+        AppError::StructsyError(e)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::IOError(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for AppError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        AppError::JoinError(e)
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+pub struct AppStateT {
+    pub db: db::Databases,
+    pub config: Config,
+    pub metrics: PrometheusHandle,
+    pub status: status::Status,
+    pub maintenance: maintenance::Maintenance,
+    pub webhook: Option<webhook::Webhook>,
+    pub events: events::Events,
+    pub shutdown: Shutdown,
+}
+
+impl AppStateT {
+    // The state of a server over `db`, with nothing started in the background yet.
+    pub fn new(db: db::Databases, config: Config, webhook: Option<webhook::Webhook>) -> AppState {
+        AppState::new(AppStateT {
+            db,
+            config,
+            metrics: metrics::install(),
+            status: status::Status::new(),
+            maintenance: maintenance::Maintenance::new(),
+            webhook,
+            events: events::Events::new(),
+            shutdown: Shutdown::new(),
+        })
+    }
+}
+
+pub type AppState = Arc<AppStateT>;
+
+// Create our own JSON extractor by wrapping `axum::Json`. This makes it easy to override the
+// rejection and provide our own which formats errors to match our application.
+//
+// `axum::Json` responds with plain text if the input is invalid.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+struct AppJson<T>(T);
+
+// Same as `AppJson`, but for query strings.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+struct AppQuery<T>(T);
+
+impl<T> IntoResponse for AppJson<T>
+where
+    axum::Json<T>: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl axum::response::IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let mut errors = Vec::new();
+        let mut retry_after = None;
+        let code = self.code();
+        let (status, message) = match self {
+            AppError::JsonRejection(rejection) => {
+                tracing::error!("bad user input -> {:?}", rejection.body_text());
+                (rejection.status(), rejection.body_text())
+            }
+            AppError::QueryRejection(rejection) => {
+                tracing::error!("bad query string -> {:?}", rejection.body_text());
+                (rejection.status(), rejection.body_text())
+            }
+            AppError::StructsyError(err) => {
+                tracing::error!("DB error -> {}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            AppError::IOError(err) => {
+                tracing::error!("I/O error -> {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "something went wrong.  Try agin later!".to_owned(),
+                )
+            }
+            AppError::JoinError(err) => {
+                tracing::error!("blocking task failed -> {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "something went wrong.  Try agin later!".to_owned(),
+                )
+            }
+            AppError::Panic(message) => {
+                tracing::error!("handler panicked -> {}", message);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "something went wrong.  Try agin later!".to_owned(),
+                )
+            }
+            AppError::Timeout(limit) => {
+                tracing::error!("request timed out after {:?}", limit);
+                (
+                    StatusCode::GATEWAY_TIMEOUT,
+                    format!("request timed out after {}s", limit.as_secs()),
+                )
+            }
+            AppError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            AppError::MethodNotAllowed => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method not allowed".to_owned(),
+            ),
+            AppError::BadRef(id) => {
+                tracing::error!("bad record id -> {}", id);
+                (StatusCode::BAD_REQUEST, format!("invalid id {}", id))
+            }
+            AppError::OutOfStock(message) => (StatusCode::CONFLICT, message),
+            AppError::Conflict(message) => (StatusCode::CONFLICT, message),
+            AppError::PaymentRequired(message) => (StatusCode::PAYMENT_REQUIRED, message),
+            AppError::Validation(problems) => {
+                errors = problems;
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "validation failed".to_owned(),
+                )
+            }
+            AppError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            AppError::UnsupportedMediaType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "the body must be sent with Content-Type: application/json".to_owned(),
+            ),
+            AppError::NotAcceptable(message) => (StatusCode::NOT_ACCEPTABLE, message),
+            AppError::RateLimited(wait) => {
+                // Retry-After is in whole seconds; round up so an early retry isn't refused again
+                let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+                retry_after = Some(secs);
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!("too many requests, retry in {}s", secs),
+                )
+            }
+            AppError::Overloaded(secs) => {
+                retry_after = Some(secs);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "the server is busy, try again shortly".to_owned(),
+                )
+            }
+            AppError::Maintenance(secs) => {
+                retry_after = Some(secs);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "down for maintenance, try again later".to_owned(),
+                )
+            }
+        };
+
+        let mut response = Problem::new(status, code, message, errors).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::MissingJsonContentType(_) => Self::UnsupportedMediaType,
+            rejection => Self::JsonRejection(rejection),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::QueryRejection(rejection)
+    }
+}
+
+// Parse a path segment into a record reference, reporting a malformed id as a client error
+// instead of letting the `StructsyError` surface as a 500.
+fn parse_ref<T: Persistent>(id: &str) -> Result<structsy::Ref<T>, AppError> {
+    id.parse().map_err(|_| AppError::BadRef(id.to_owned()))
+}
+
+// How long a task may wait for a thread of the blocking pool before its request gets a 503.
+const BLOCKING_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+const BLOCKING_RETRY_AFTER_SECS: u64 = 1;
+
+// Structsy calls are synchronous, so run them on tokio's blocking pool to keep a slow scan
+// or commit from stalling the async workers.
+//
+// With every thread of the pool busy, tasks queue.  One still queued after
+// `BLOCKING_QUEUE_TIMEOUT` is given up: the request answers 503, and `f` never runs,
+// so a refused write is not done after all.  Once `f` has started it is waited for, leaving
+// a slow `f` to the request timeout.
+async fn run_blocking<F, R>(f: F) -> Result<R, AppError>
+where
+    F: FnOnce() -> Result<R, AppError> + Send + 'static,
+    R: Send + 'static,
+{
+    // whichever of the task and the timeout sets it first decides whether `f` runs
+    let claimed = Arc::new(AtomicBool::new(false));
+    let (started, on_start) = tokio::sync::oneshot::channel();
+    let task = tokio::task::spawn_blocking({
+        let claimed = claimed.clone();
+        move || {
+            if claimed.swap(true, Ordering::AcqRel) {
+                return Err(AppError::Overloaded(BLOCKING_RETRY_AFTER_SECS));
+            }
+            let _ = started.send(());
+            f()
+        }
+    });
+    let waited = tokio::time::timeout(BLOCKING_QUEUE_TIMEOUT, on_start).await;
+    if waited.is_err() && !claimed.swap(true, Ordering::AcqRel) {
+        metrics::count_blocking_timeout();
+        tracing::warn!(
+            "no blocking thread free after {:?}, refusing the request",
+            BLOCKING_QUEUE_TIMEOUT
+        );
+        return Err(AppError::Overloaded(BLOCKING_RETRY_AFTER_SECS));
+    }
+    task.await?
+}
+
+// Attempts at a transaction that keeps losing to concurrent ones, and the pause before the
+// first retry; each further retry waits twice as long.
+const TX_ATTEMPTS: u32 = 3;
+const TX_BACKOFF: Duration = Duration::from_millis(10);
+
+// Held for the whole of each write transaction.  Structsy opens persy with its default
+// last-write-wins strategy, under which a commit never fails because a record changed since
+// it was read, so two concurrent read-modify-writes (two purchases of the same drink, say)
+// would both succeed and one of the decrements would be lost.  Taking turns keeps what a
+// transaction read true until it commits.
+static WRITE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+fn write_turn() -> std::sync::MutexGuard<'static, ()> {
+    // nothing is left half-done by a panicking holder, so a poisoned lock is still usable
+    WRITE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Run `f` with no write transaction open and none able to start, e.g. to copy the database
+// file.  Blocks like `with_tx`.
+fn with_writes_paused<R>(f: impl FnOnce() -> R) -> R {
+    let _turn = write_turn();
+    f()
+}
+
+// Run `f` in a fresh transaction and commit it.  When the transaction loses to a concurrent
+// one (see `retryable`) the whole of it, `f` included, is run again, up to `TX_ATTEMPTS`
+// times; any other error, from `f` or the commit, is returned straight away and the
+// transaction is rolled back.  Blocks the thread while waiting for `WRITE_LOCK` or backing
+// off, so call it from `run_blocking`.
+fn with_tx<R>(
+    file: &db::DbFile,
+    mut f: impl FnMut(&mut OwnedSytx) -> Result<R, AppError>,
+) -> Result<R, AppError> {
+    in_turns(|| {
+        let mut tx = file.connection().begin()?;
+        let value = f(&mut tx)?;
+        tx.commit()?;
+        Ok(value)
+    })
+}
+
+// `with_tx` for a write spanning several database files: `f` gets a transaction on each of
+// `files`, in the same order, and they commit in two phases.  Every one is prepared
+// before any is committed, so a failure up to there (a conflict included) rolls them all
+// back.  Only a commit failing once prepared, which takes an I/O error, can leave some files
+// committed; that is logged and returned.
+fn with_txs<R>(
+    files: &[&db::DbFile],
+    mut f: impl FnMut(&mut [OwnedSytx]) -> Result<R, AppError>,
+) -> Result<R, AppError> {
+    in_turns(|| {
+        let mut txs = files
+            .iter()
+            .map(|file| file.connection().begin())
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = f(&mut txs)?;
+        let mut prepared = Vec::with_capacity(txs.len());
+        for tx in txs {
+            match tx.prepare_commit() {
+                Ok(tx) => prepared.push(tx),
+                Err(err) => {
+                    for tx in prepared {
+                        if let Err(err) = tx.rollback() {
+                            tracing::error!(
+                                "failed to roll a prepared transaction back -> {}",
+                                err
+                            );
+                        }
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+        for (committed, tx) in prepared.into_iter().enumerate() {
+            if let Err(err) = tx.commit() {
+                tracing::error!(
+                    "transaction failed to commit with {} of {} files committed -> {}",
+                    committed,
+                    files.len(),
+                    err
+                );
+                return Err(err.into());
+            }
+        }
+        Ok(value)
+    })
+}
+
+// Run `attempt`, a whole transaction, holding `WRITE_LOCK`, again while it loses to a
+// concurrent one, as `with_tx` describes.
+fn in_turns<R>(mut attempt: impl FnMut() -> Result<R, AppError>) -> Result<R, AppError> {
+    let mut tries = 1;
+    let mut backoff = TX_BACKOFF;
+    loop {
+        let turn = write_turn();
+        let result = attempt();
+        drop(turn);
+        match result {
+            Err(AppError::StructsyError(err)) if tries < TX_ATTEMPTS && retryable(&err) => {
+                tracing::debug!(
+                    "transaction conflict on attempt {}, retrying -> {}",
+                    tries,
+                    err
+                );
+                std::thread::sleep(backoff);
+                tries += 1;
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+// `with_tx` for `?dry_run=true`: `f` runs against the same data, taking its turn and retried
+// the same way, but its transaction is rolled back rather than committed.  Inserting would
+// still hand out an id, so `f` must not.
+fn with_tx_or_dry_run<R>(
+    file: &db::DbFile,
+    dry_run: bool,
+    mut f: impl FnMut(&mut OwnedSytx) -> Result<R, AppError>,
+) -> Result<R, AppError> {
+    if !dry_run {
+        return with_tx(file, f);
+    }
+    // dropped without a commit, which rolls it back
+    in_turns(|| f(&mut file.connection().begin()?))
+}
+
+// Transient failures worth a retry: persy's `TransactionTimeout`, where the records' locks
+// stayed held by others for too long, and `VersionNotLastest`, where a record this transaction
+// changed was committed by another one first (raised only by persy's versioned strategies,
+// should structsy ever open it with one).  Everything else (missing records, I/O,
+// schema errors...) would fail the same way again.
+fn retryable(err: &StructsyError) -> bool {
+    matches!(
+        err,
+        StructsyError::PersyError(PersyError::VersionNotLastest | PersyError::TransactionTimeout)
+    )
+}
+
+// `DB_PATH` value selecting a throwaway in-memory database, e.g. for tests.
+pub const MEMORY_DB: &str = ":memory:";
+
+// Open (migrating if needed) the database at `path`, or an empty in-memory one for
+// `MEMORY_DB`, with the schema defined.
+pub fn open_db(path: &str) -> Result<Structsy, StructsyError> {
+    let connection = if path == MEMORY_DB {
+        Structsy::memory()?
+    } else {
+        beverage::legacy::open(path)?
+    };
+    define_schema(&connection)?;
+    Ok(connection)
+}
+
+// Define every persistent type once at startup; the handlers assume the schema exists.
+fn define_schema(connection: &Structsy) -> Result<(), StructsyError> {
+    connection.define::<Coffee>()?;
+    connection.define::<Beer>()?;
+    connection.define::<Soda>()?;
+    connection.define::<Sale>()?;
+    connection.define::<reservation::Reservation>()?;
+    connection.define::<cart::Cart>()?;
+    connection.define::<idempotency::IdempotencyRecord>()?;
+    connection.define::<audit::AuditEntry>()?;
+    connection.define::<webhook::PendingWebhook>()?;
+    Ok(())
+}
+
+// Whether every type `define_schema` defines is there.
+fn schema_defined(connection: &Structsy) -> Result<bool, StructsyError> {
+    Ok(connection.is_defined::<Coffee>()?
+        && connection.is_defined::<Beer>()?
+        && connection.is_defined::<Soda>()?
+        && connection.is_defined::<Sale>()?
+        && connection.is_defined::<reservation::Reservation>()?
+        && connection.is_defined::<cart::Cart>()?
+        && connection.is_defined::<idempotency::IdempotencyRecord>()?
+        && connection.is_defined::<audit::AuditEntry>()?
+        && connection.is_defined::<webhook::PendingWebhook>()?)
+}
+
+// `CatchPanicLayer` handler: report the panic like any other internal error instead of
+// dropping the connection.
+pub fn handle_panic(payload: Box<dyn Any + Send + 'static>) -> Response {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    };
+    AppError::Panic(message).into_response()
+}
+
+// Give up on requests that take longer than `limit`.  A blocking database task already
+// started keeps running to completion; only the response is abandoned.
+pub async fn timeout(
+    State(limit): State<Duration>,
+    req: Request,
+    next: axum::middleware::Next,
+) -> Result<Response, AppError> {
+    tokio::time::timeout(limit, next.run(req))
+        .await
+        .map_err(|_| AppError::Timeout(limit))
+}
+
+// Prefix of the current API version.  Every API route lives under it; the probes don't.
+pub const API_PREFIX: &str = "/v1";
+
+// The routes from before `/v1` still answer, for one release, with a `Warning` naming the
+// versioned path.  They are removed in the release after.
+async fn deprecated(req: Request, next: axum::middleware::Next) -> Response {
+    let successor = format!("{}{}", API_PREFIX, req.uri().path());
+    let mut response = next.run(req).await;
+    let warning = format!(
+        "299 - \"unversioned paths are deprecated and go away in the next release; use {}\"",
+        successor
+    );
+    if let Ok(warning) = HeaderValue::try_from(warning) {
+        response.headers_mut().insert(header::WARNING, warning);
+    }
+    response
+}
+
+// Unknown routes and methods answer with a problem document like every other error.
+async fn handler_404() -> AppError {
+    AppError::NotFound("route not found".to_owned())
+}
+
+async fn handler_405() -> AppError {
+    AppError::MethodNotAllowed
+}
+
+#[derive(Serialize, ToSchema)]
+struct Health {
+    status: &'static str,
+}
+
+// Liveness check: the process is up and answering.  It touches nothing else, so a slow or
+// broken database gets the server taken out of rotation by `/ready` instead of restarted.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "meta",
+    responses((status = 200, description = "Up", body = Health))
+)]
+async fn health() -> AppJson<Health> {
+    AppJson(Health { status: "ok" })
+}
+
+// Readiness check: 503 until the server listens with the store opened, its schema defined and
+// seeded, and again from the shutdown signal on while requests drain.  In between it also
+// makes sure the database answers and still has every type of the schema.
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "meta",
+    responses(
+        (status = 200, description = "Ready for traffic", body = Health),
+        (
+            status = 503,
+            description = "Starting, shutting down, or the database doesn't answer",
+            body = Health,
+        ),
+    )
+)]
+async fn ready(State(state): State<AppState>) -> (StatusCode, AppJson<Health>) {
+    let unavailable = |status| (StatusCode::SERVICE_UNAVAILABLE, AppJson(Health { status }));
+    if !state.status.is_ready() {
+        return unavailable("not ready");
+    }
+    let ping = run_blocking(move || {
+        for file in state.db.files() {
+            if !schema_defined(&file.connection())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })
+    .await;
+    match ping {
+        Ok(true) => (StatusCode::OK, AppJson(Health { status: "ready" })),
+        Ok(false) => {
+            tracing::error!("readiness check failed -> the schema is incomplete");
+            unavailable("degraded")
+        }
+        Err(err) => {
+            tracing::error!("readiness check failed -> {}", err);
+            unavailable("degraded")
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct Version {
+    version: &'static str,
+    git_sha: &'static str,
+    build_time: String,
+}
+
+// What is deployed, as captured by build.rs.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "meta",
+    responses((status = 200, description = "The running build", body = Version))
+)]
+async fn version() -> AppJson<Version> {
+    let build_time = env!("BUILD_TIME")
+        .parse()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default();
+    AppJson(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        build_time,
+    })
+}
+
+// gzip/br when the client accepts it.  Small bodies like `/health` aren't worth the CPU, and
+// the content types tower-http skips by default stay skipped.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        SizeAbove::new(MIN_COMPRESS_BYTES)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::SSE),
+    )
+}
+
+// Let a browser frontend on another origin call the API, preflight included.
+fn cors_layer(config: &Config) -> CorsLayer {
+    let origins = if config.allowed_origins.is_empty() {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.allowed_origins.clone())
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::PATCH,
+        ])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+            HeaderName::from_static("x-request-id"),
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("idempotency-key"),
+        ])
+        .expose_headers([
+            header::ETAG,
+            header::LAST_MODIFIED,
+            header::LOCATION,
+            header::RETRY_AFTER,
+            HeaderName::from_static("idempotent-replayed"),
+            HeaderName::from_static("x-request-id"),
+        ])
+}
+
+// Every route and middleware, wired to `state`, ready to be served or driven directly with
+// `tower::ServiceExt::oneshot`.
+pub fn build_router(state: AppState) -> Router {
+    let api = Router::new()
+        .nest("/coffee", beverage::routes::<Coffee>(state.clone()))
+        .nest("/beer", beverage::routes::<Beer>(state.clone()))
+        .nest("/soda", beverage::routes::<Soda>(state.clone()))
+        .merge(beverage::drink_routes(state.clone()))
+        .merge(export::routes(state.clone()))
+        .merge(audit::routes(state.clone()))
+        .merge(sale::routes(state.clone()))
+        .merge(cart::routes(state.clone()))
+        .merge(openapi::routes())
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::guard,
+        ))
+        .merge(maintenance::routes(state.clone()))
+        .merge(admin::routes(state.clone()))
+        // the probes, merged below, stay outside so an overloaded server can still be watched
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(load_shed::ConcurrencyLimits::new(
+                state.config.read_concurrency,
+                state.config.write_concurrency,
+            )),
+            load_shed::shed,
+        ))
+        // an open stream would hold a permit for as long as the dashboard stays up
+        .merge(
+            events::routes(state.clone()).layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                maintenance::guard,
+            )),
+        );
+    let mut app = Router::new()
+        .nest(API_PREFIX, api.clone())
+        .merge(api.layer(axum::middleware::from_fn(deprecated)))
+        .fallback(handler_404)
+        .method_not_allowed_fallback(handler_405)
+        .route_layer(axum::middleware::from_fn(metrics::track_latency));
+
+    // A panicking handler still gets a JSON 500 and the server keeps running
+    app = app.layer(CatchPanicLayer::custom(handle_panic));
+
+    app = app.layer(axum::middleware::from_fn_with_state(
+        state.config.request_timeout,
+        timeout,
+    ));
+
+    // Cap what the body extractors will buffer.  Unlike tower-http's `RequestBodyLimitLayer`,
+    // which answers in plain text, this fails the extractor so the 413 goes through `AppError`.
+    app = app.layer(DefaultBodyLimit::max(state.config.body_limit));
+
+    if state.config.rate_limit > 0.0 {
+        let limiter = Arc::new(rate_limit::RateLimiter::new(
+            state.config.rate_limit,
+            state.config.rate_limit_burst,
+        ));
+        app = app.layer(axum::middleware::from_fn_with_state(
+            limiter,
+            rate_limit::limit,
+        ));
+    }
+
+    if state.config.log_bodies {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            state.config.body_limit,
+            body_log::log_bodies,
+        ));
+    }
+
+    let x_request_id = HeaderName::from_static("x-request-id");
+
+    // Basic access logging
+    app = app.layer(
+        TraceLayer::new_for_http().make_span_with(move |req: &Request<_>| {
+            const REQUEST_ID: &str = "request_id";
+
+            let method = req.method();
+            let uri = req.uri();
+            let request_id = req
+                .headers()
+                .get(&x_request_id)
+                .and_then(|id| id.to_str().ok());
+
+            let span = error_span!("request", %method, %uri, { REQUEST_ID } = field::Empty);
+
+            if let Some(request_id) = request_id {
+                span.record(REQUEST_ID, field::display(request_id));
+            }
+
+            span
+        }),
+    );
+
+    let x_request_id = HeaderName::from_static("x-request-id");
+
+    // propagate `x-request-id` headers from request to response
+    app = app.layer(PropagateRequestIdLayer::new(x_request_id.clone()));
+
+    app = app.layer(SetRequestIdLayer::new(
+        x_request_id.clone(),
+        MakeRequestUuid,
+    ));
+
+    // Added after the layers so probes, version checks and scrapes don't flood the access log
+    // or get rate limited
+    app = app.merge(
+        Router::new()
+            .route("/health", get(health))
+            .route("/ready", get(ready))
+            .route("/version", get(version))
+            .route("/status", get(status::status))
+            .route("/metrics", get(metrics::render))
+            .method_not_allowed_fallback(handler_405)
+            .with_state(state.clone()),
+    );
+
+    app = app.layer(axum::middleware::from_fn(problem::add_context));
+
+    app = app.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        status::count_request,
+    ));
+
+    app = app.layer(compression_layer());
+
+    // Outermost, so preflight requests are answered before anything else runs
+    app = app.layer(cors_layer(&state.config));
+
+    // `/v1/coffee/list/` is served like `/v1/coffee/list`.  Layers on a `Router` only run once a
+    // route has matched, so the rewrite wraps the whole app, mounted as an empty router's fallback.
+    Router::new().fallback_service(NormalizePathLayer::trim_trailing_slash().layer(app))
+}
+
+// Bind `addr` and serve `app` until a shutdown signal, then give in-flight requests up to
+// `drain_timeout` to finish.  `/ready` answers 200 from the bind until the signal.
+pub async fn serve(
+    app: Router,
+    addr: SocketAddr,
+    drain_timeout: Duration,
+    state: AppState,
+) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Listening on {}", addr);
+    state.status.set_ready(true);
+
+    // Stop accepting connections on the first signal and let in-flight handlers finish, but
+    // don't wait on them for longer than `drain_timeout`.
+    let draining = Arc::new(Notify::new());
+    // The rate limiter keys clients by peer address
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let server = axum::serve(listener, app).with_graceful_shutdown({
+        let draining = draining.clone();
+        async move {
+            shutdown_signal().await;
+            state.status.set_ready(false);
+            state.shutdown.start();
+            draining.notify_one();
+        }
+    });
+    tokio::select! {
+        res = async { server.await } => res,
+        _ = async {
+            draining.notified().await;
+            tokio::time::sleep(drain_timeout).await;
+        } => {
+            tracing::warn!("in-flight requests still running after {:?}, exiting", drain_timeout);
+            Ok(())
+        }
+    }
+}
+
+// Set once `serve` gets the shutdown signal, for the responses that would otherwise outlive
+// the drain, like the `/events` streams.  They all wait on this one flag rather than each
+// listening for the signal.
+pub struct Shutdown(watch::Sender<bool>);
+
+impl Shutdown {
+    fn new() -> Self {
+        Shutdown(watch::channel(false).0)
+    }
+
+    pub fn start(&self) {
+        self.0.send_replace(true);
+    }
+
+    // Resolves once `start` has been called, at once when it already has.
+    pub fn started(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut started = self.0.subscribe();
+        async move {
+            // the sender lives in the state, as long as any response
+            let _ = started.wait_for(|started| *started).await;
+        }
+    }
+}
+
+// Resolves on ctrl-c or, on unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutting down");
+}
+
+// `to_stderr` keeps stdout clean for commands that print their result there.  With a
+// `telemetry` exporter spans also go to the collector, next to the console output.
+fn init_logging(format: LogFormat, to_stderr: bool, telemetry: Option<&telemetry::Telemetry>) {
+    let writer = if to_stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let output = match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        // span fields, `request_id` among them, go in each line's `span` object
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .json()
+            .with_span_list(false)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "vending_structsy=debug,tower_http=debug".into()),
+        )
+        .with(output)
+        .with(telemetry.map(|telemetry| telemetry.layer()))
+        .init();
+}
+
+// The whole process: the command line, then the one-shot command or the server.
+pub async fn run() {
+    let cli = cli::Cli::parse();
+    let config = Config::from_env().map(|config| cli.apply(config));
+    // a bad configuration is still reported, in the default format
+    let serving = matches!(cli.command, None | Some(cli::Command::Serve));
+    // only the server's spans are worth a trace; the one-shot commands exit too soon to flush
+    let endpoint = config
+        .as_ref()
+        .ok()
+        .and_then(|c| c.otlp_endpoint.as_deref())
+        .filter(|_| serving);
+    let telemetry = endpoint.map(|endpoint| (endpoint, telemetry::Telemetry::new(endpoint)));
+    init_logging(
+        config
+            .as_ref()
+            .map_or(LogFormat::default(), |c| c.log_format),
+        !serving,
+        telemetry.as_ref().and_then(|(_, t)| t.as_ref().ok()),
+    );
+    let telemetry = match telemetry {
+        Some((endpoint, Ok(telemetry))) => {
+            tracing::info!("exporting traces to {}", endpoint);
+            Some(telemetry)
+        }
+        Some((endpoint, Err(err))) => {
+            tracing::error!("not exporting traces to {} -> {}", endpoint, err);
+            None
+        }
+        None => None,
+    };
+    let config = config.unwrap_or_else(|err| {
+        tracing::error!("invalid configuration -> {}", err);
+        std::process::exit(1);
+    });
+
+    let db = db::Databases::open(&config).unwrap_or_else(|err| {
+        tracing::error!("{}", err);
+        std::process::exit(1);
+    });
+
+    if let Some(command) = cli.command.filter(|_| !serving) {
+        if let Err(err) = cli::run(command, &db) {
+            tracing::error!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if config.api_key.is_none() {
+        if !config.auth_disabled {
+            tracing::error!(
+                "API_KEY is not set; set it, or AUTH_DISABLED=true to run without one locally"
+            );
+            std::process::exit(1);
+        }
+        tracing::warn!("AUTH_DISABLED is set, the guarded routes are open to anyone");
+    }
+
+    if config.seed {
+        match seed::seed(&db) {
+            Ok(true) => tracing::info!("seeded the empty store with sample drinks"),
+            Ok(false) => tracing::info!("SEED is set but the store has drinks, not seeding"),
+            Err(err) => {
+                tracing::error!("failed to seed the store -> {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if config.log_bodies {
+        tracing::warn!("LOG_BODIES is set, request and response bodies are logged");
+    }
+
+    let webhook = config.webhook_url.as_deref().map(|url| {
+        webhook::Webhook::new(url, db.shared().clone()).unwrap_or_else(|err| {
+            tracing::error!("failed to set up the webhook for {} -> {}", url, err);
+            std::process::exit(1);
+        })
+    });
+
+    let state = AppStateT::new(db, config, webhook);
+
+    tokio::spawn(reservation::release_expired(state.clone()));
+    if let Some(webhook) = &state.webhook {
+        webhook.drain_pending();
+    }
+
+    let (addr, drain_timeout) = (state.config.bind_addr, state.config.shutdown_timeout);
+    let served = serve(build_router(state.clone()), addr, drain_timeout, state).await;
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+    if let Err(err) = served {
+        tracing::error!("server failed on {} -> {}", addr, err);
+        std::process::exit(1);
+    }
+}
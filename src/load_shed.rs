@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use tokio::sync::Semaphore;
+
+use crate::AppError;
+
+// Caps on the API requests in flight, reads and writes counted apart so a burst of one can't
+// starve the other.  Past a cap requests are turned away with a 503 rather than queued, each
+// of them would otherwise hold a blocking-pool thread waiting on the database.
+pub struct ConcurrencyLimits {
+    reads: Option<Semaphore>,
+    writes: Option<Semaphore>,
+}
+
+impl ConcurrencyLimits {
+    // A limit of 0 lets that kind of request through unlimited.
+    pub fn new(reads: usize, writes: usize) -> Self {
+        let semaphore = |permits| (permits > 0).then(|| Semaphore::new(permits));
+        ConcurrencyLimits {
+            reads: semaphore(reads),
+            writes: semaphore(writes),
+        }
+    }
+}
+
+// Seconds a shed client is told to wait; the requests ahead of it finish in well under that.
+const RETRY_AFTER_SECS: u64 = 1;
+
+pub async fn shed(
+    State(limits): State<Arc<ConcurrencyLimits>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let (limit, kind) = if read {
+        (&limits.reads, "reads")
+    } else {
+        (&limits.writes, "writes")
+    };
+    let Some(limit) = limit else {
+        return Ok(next.run(req).await);
+    };
+    let Ok(_permit) = limit.try_acquire() else {
+        tracing::warn!("too many concurrent {}, shedding {}", kind, req.uri());
+        return Err(AppError::Overloaded(RETRY_AFTER_SECS));
+    };
+    Ok(next.run(req).await)
+}
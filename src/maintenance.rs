@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::problem::Problem;
+use crate::{auth, AppError, AppJson, AppState};
+
+const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
+// Whether the API is down for maintenance, switched at runtime through `/maintenance`.
+pub struct Maintenance {
+    enabled: AtomicBool,
+    // reads are refused too, not only writes
+    block_reads: AtomicBool,
+    // sent as `Retry-After` to the refused requests
+    retry_after_secs: AtomicU64,
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Maintenance {
+            enabled: AtomicBool::new(false),
+            block_reads: AtomicBool::new(false),
+            retry_after_secs: AtomicU64::new(DEFAULT_RETRY_AFTER_SECS),
+        }
+    }
+
+    fn mode(&self) -> Mode {
+        Mode {
+            enabled: self.enabled.load(Ordering::Relaxed),
+            block_reads: self.block_reads.load(Ordering::Relaxed),
+            retry_after_secs: self.retry_after_secs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Layer over the API routes: while maintenance is on, writes (and reads too with
+// `block_reads`) answer 503.  The probes and `/maintenance` itself stay reachable.
+pub async fn guard(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let mode = state.maintenance.mode();
+    let read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if mode.enabled && (mode.block_reads || !read) {
+        return Err(AppError::Maintenance(mode.retry_after_secs));
+    }
+    Ok(next.run(req).await)
+}
+
+// Body of `PUT /maintenance`, and what both methods answer with.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct Mode {
+    enabled: bool,
+    #[serde(default)]
+    block_reads: bool,
+    #[serde(default = "default_retry_after")]
+    retry_after_secs: u64,
+}
+
+fn default_retry_after() -> u64 {
+    DEFAULT_RETRY_AFTER_SECS
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance",
+    tag = "maintenance",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The current mode", body = Mode),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn current(State(state): State<AppState>) -> AppJson<Mode> {
+    AppJson(state.maintenance.mode())
+}
+
+#[utoipa::path(
+    put,
+    path = "/maintenance",
+    tag = "maintenance",
+    request_body = Mode,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The mode now in force", body = Mode),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn switch(State(state): State<AppState>, AppJson(mode): AppJson<Mode>) -> AppJson<Mode> {
+    let maintenance = &state.maintenance;
+    maintenance
+        .retry_after_secs
+        .store(mode.retry_after_secs, Ordering::Relaxed);
+    maintenance
+        .block_reads
+        .store(mode.block_reads, Ordering::Relaxed);
+    maintenance.enabled.store(mode.enabled, Ordering::Relaxed);
+    if mode.enabled {
+        tracing::warn!(
+            "maintenance mode on, refusing {}",
+            if mode.block_reads {
+                "all requests"
+            } else {
+                "writes"
+            }
+        );
+    } else {
+        tracing::warn!("maintenance mode off");
+    }
+    AppJson(mode)
+}
+
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/maintenance", get(current).put(switch))
+        .route_layer(from_fn_with_state(state.clone(), auth::require_api_key))
+        .with_state(state)
+}
@@ -0,0 +1,99 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{AppError, AppState};
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+// The global Prometheus recorder, installed by the first call; metrics recorded before it are
+// lost.  Later calls, from the tests' many apps in one process, get the same handle.
+pub fn install() -> PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full("http_request_duration_seconds".to_owned()),
+                    LATENCY_BUCKETS,
+                )
+                .expect("latency buckets are not empty")
+                .install_recorder()
+                .expect("failed to install the Prometheus recorder")
+        })
+        .clone()
+}
+
+// Count a create/update/delete by drink type and outcome.
+pub fn count_write<R>(kind: &'static str, op: &'static str, result: &Result<R, AppError>) {
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    ::metrics::counter!(
+        "beverage_writes_total",
+        "kind" => kind,
+        "op" => op,
+        "outcome" => outcome
+    )
+    .increment(1);
+}
+
+// Count a purchase that left a drink at or below the low-stock threshold.
+pub fn count_low_stock(kind: &'static str) {
+    ::metrics::counter!("low_stock_alerts_total", "kind" => kind).increment(1);
+}
+
+// Count a request refused because no thread of the blocking pool came free in time.
+pub fn count_blocking_timeout() {
+    ::metrics::counter!("blocking_pool_timeouts_total").increment(1);
+}
+
+// Route layer recording how long each handler took, labelled by route template and status.
+pub async fn track_latency(req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let status = response.status().as_u16().to_string();
+
+    ::metrics::histogram!(
+        "http_request_duration_seconds",
+        "method" => method,
+        "path" => path,
+        "status" => status
+    )
+    .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+// Prometheus text exposition.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "meta",
+    responses((
+        status = 200,
+        description = "Prometheus metrics",
+        body = String,
+        content_type = "text/plain",
+    ))
+)]
+pub async fn render(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
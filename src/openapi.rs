@@ -0,0 +1,205 @@
+use std::sync::LazyLock;
+
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use utoipa::openapi::{
+    content::ContentBuilder,
+    path::{OperationBuilder, Parameter, ParameterBuilder, ParameterIn},
+    request_body::{RequestBody, RequestBodyBuilder},
+    response::{Response, ResponseBuilder},
+    schema::{ArrayBuilder, Components, ObjectBuilder, Ref, Schema, Type},
+    security::{ApiKey, ApiKeyValue, SecurityRequirement, SecurityScheme},
+    RefOr, Required,
+};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+use crate::beverage::{self, Beer, Coffee, Soda};
+use crate::problem::Problem;
+use crate::API_PREFIX;
+
+// Security scheme of the routes behind `auth::require_api_key`.
+const API_KEY: &str = "api_key";
+
+// The handlers with a fixed path are annotated where they are defined.  The per-kind routes
+// are generic, which `#[utoipa::path]` can't describe, so `beverage::openapi` builds theirs.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "vending_structsy",
+        description = "Stock, sales and history of a vending machine fleet",
+        license(name = "MIT")
+    ),
+    paths(
+        crate::health,
+        crate::ready,
+        crate::version,
+        crate::status::status,
+        crate::metrics::render,
+    ),
+    components(schemas(Problem)),
+    modifiers(&ApiKeyScheme)
+)]
+struct ApiDoc;
+
+// What `build_router` nests under `API_PREFIX`.  The deprecated unversioned copies aren't
+// documented.
+#[derive(OpenApi)]
+#[openapi(paths(
+    spec,
+    beverage::drinks,
+    crate::export::export,
+    crate::export::import,
+    crate::audit::recent,
+    crate::sale::daily,
+    crate::cart::create,
+    crate::cart::fetch,
+    crate::cart::add_item,
+    crate::cart::remove_item,
+    crate::cart::checkout,
+    crate::maintenance::current,
+    crate::maintenance::switch,
+    crate::admin::backup,
+    crate::admin::compact,
+    crate::events::events,
+))]
+struct VersionedApi;
+
+struct ApiKeyScheme;
+
+impl Modify for ApiKeyScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Components::new)
+            .add_security_scheme(
+                API_KEY,
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+            );
+    }
+}
+
+// Built on first request; the routes don't change while the process runs.
+static SPEC: LazyLock<String> = LazyLock::new(|| {
+    let api = VersionedApi::openapi()
+        .nest("/coffee", beverage::openapi::<Coffee>())
+        .nest("/beer", beverage::openapi::<Beer>())
+        .nest("/soda", beverage::openapi::<Soda>());
+    ApiDoc::openapi()
+        .nest(API_PREFIX, api)
+        .to_pretty_json()
+        .expect("the spec serializes")
+});
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "meta",
+    responses((status = 200, description = "This document", content_type = "application/json"))
+)]
+async fn spec() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], SPEC.as_str())
+}
+
+pub fn routes() -> Router {
+    Router::new().route("/openapi.json", get(spec))
+}
+
+// Helpers for describing the generic routes by hand.
+
+// Add `S` and every schema it refers to.
+pub fn register<S: ToSchema>(components: &mut Components) {
+    let mut schemas = vec![(S::name().into_owned(), S::schema())];
+    S::schemas(&mut schemas);
+    components.schemas.extend(schemas);
+}
+
+pub fn schema<S: ToSchema>() -> RefOr<Schema> {
+    Ref::from_schema_name(S::name()).into()
+}
+
+pub fn array(items: RefOr<Schema>) -> RefOr<Schema> {
+    ArrayBuilder::new().items(items).into()
+}
+
+pub fn string() -> RefOr<Schema> {
+    ObjectBuilder::new().schema_type(Type::String).into()
+}
+
+pub fn operation(tag: &str, summary: &str) -> OperationBuilder {
+    OperationBuilder::new().tag(tag).summary(Some(summary))
+}
+
+// An operation behind the API key.
+pub fn admin(operation: OperationBuilder) -> OperationBuilder {
+    operation
+        .security(SecurityRequirement::new(API_KEY, Vec::<String>::new()))
+        .response("401", problem("Missing or wrong X-API-Key"))
+}
+
+pub fn query<P: IntoParams>() -> Vec<Parameter> {
+    P::into_params(|| Some(ParameterIn::Query))
+}
+
+// The `{id}` of the single-record routes.
+pub fn id() -> Parameter {
+    path_param("id")
+}
+
+pub fn path_param(name: &str) -> Parameter {
+    ParameterBuilder::new()
+        .name(name)
+        .parameter_in(ParameterIn::Path)
+        .required(Required::True)
+        .schema(Some(string()))
+        .build()
+}
+
+// An optional request header.
+pub fn header_param(name: &str) -> Parameter {
+    ParameterBuilder::new()
+        .name(name)
+        .parameter_in(ParameterIn::Header)
+        .required(Required::False)
+        .schema(Some(string()))
+        .build()
+}
+
+pub fn json_body(schema: RefOr<Schema>) -> RequestBody {
+    RequestBodyBuilder::new()
+        .content(
+            "application/json",
+            ContentBuilder::new().schema(Some(schema)).build(),
+        )
+        .required(Some(Required::True))
+        .build()
+}
+
+pub fn ok(description: &str, content_type: &str, schema: RefOr<Schema>) -> Response {
+    ResponseBuilder::new()
+        .description(description)
+        .content(
+            content_type,
+            ContentBuilder::new().schema(Some(schema)).build(),
+        )
+        .build()
+}
+
+// `response`, which can also be sent as `content_type`.
+pub fn also(mut response: Response, content_type: &str, schema: RefOr<Schema>) -> Response {
+    response.content.insert(
+        content_type.to_owned(),
+        ContentBuilder::new().schema(Some(schema)).build(),
+    );
+    response
+}
+
+pub fn json(description: &str, schema: RefOr<Schema>) -> Response {
+    ok(description, "application/json", schema)
+}
+
+pub fn empty(description: &str) -> Response {
+    ResponseBuilder::new().description(description).build()
+}
+
+pub fn problem(description: &str) -> Response {
+    ok(description, "application/problem+json", schema::<Problem>())
+}
@@ -0,0 +1,96 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use utoipa::openapi::{schema::Type, ObjectBuilder, RefOr, Schema};
+use utoipa::ToSchema;
+
+use crate::AppError;
+
+// An RFC 7807 problem document, the body of every error response.
+#[derive(Serialize, Clone, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    // Extension member: what went wrong, as one of `AppError::CODES`
+    #[schema(schema_with = code_schema)]
+    code: &'static str,
+    detail: String,
+    // `instance` and `request_id` are filled in by `add_context`, the only place that sees
+    // the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    // Extension member: the `x-request-id` of the failed request, to find it in the logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    // Extension member: one entry per problem found by validation
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+fn code_schema() -> RefOr<Schema> {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .enum_values(Some(AppError::CODES.iter().copied()))
+        .into()
+}
+
+impl Problem {
+    pub fn new(
+        status: StatusCode,
+        code: &'static str,
+        detail: String,
+        errors: Vec<String>,
+    ) -> Self {
+        Problem {
+            // no problem types are defined, so the title is just the status' reason
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            code,
+            detail,
+            instance: None,
+            request_id: None,
+            errors,
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = (status, axum::Json(&self)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        // kept so `add_context` can rewrite the body once the request path is known
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+// Outer layer completing every problem response with the request path and id.  It runs
+// outside the request id layers, so the id is taken from the response they stamped.
+pub async fn add_context(req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_owned();
+    let mut response = next.run(req).await;
+    let Some(mut problem) = response.extensions_mut().remove::<Problem>() else {
+        return response;
+    };
+    problem.instance = Some(path);
+    problem.request_id = response
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_owned);
+    let body = axum::Json(&problem).into_response().into_body();
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body)
+}
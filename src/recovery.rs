@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use persy::PersyError;
+use structsy::{Structsy, StructsyError};
+
+use crate::admin::BACKUP_TIME;
+use crate::config::{AutoRecover, Config};
+use crate::{open_db, MEMORY_DB};
+
+// Open the database file at `path` like `open_db`.  When it turns out to be damaged,
+// DB_AUTO_RECOVER decides: give up (the default), move it aside and start empty, or move it
+// aside and start from its newest copy in BACKUP_DIR.  The damaged file is kept as
+// `<path>.damaged-<time>`.
+pub fn open(config: &Config, path: &str) -> Result<Structsy, String> {
+    let describe = |err: StructsyError| format!("failed to open database {} -> {}", path, err);
+    let err = match open_db(path) {
+        Ok(connection) => return Ok(connection),
+        Err(err) if path != MEMORY_DB && damaged(&err) => err,
+        Err(err) => return Err(describe(err)),
+    };
+    tracing::error!("database {} looks damaged -> {}", path, err);
+    let backup = match config.auto_recover {
+        AutoRecover::Off => {
+            return Err(format!(
+                "{}; set DB_AUTO_RECOVER=fresh or backup to move it aside and start anyway",
+                describe(err)
+            ))
+        }
+        AutoRecover::Fresh => None,
+        // looked for before anything is moved, so a missing backup leaves the file alone
+        AutoRecover::Backup => Some(latest_backup(path, &config.backup_dir)?),
+    };
+    let aside = format!(
+        "{}.damaged-{}",
+        path,
+        chrono::Utc::now().format(BACKUP_TIME)
+    );
+    std::fs::rename(path, &aside)
+        .map_err(|err| format!("failed to move {} to {} -> {}", path, aside, err))?;
+    tracing::warn!("moved the damaged database to {}", aside);
+    if let Some(backup) = backup {
+        std::fs::copy(&backup, path)
+            .map_err(|err| format!("failed to copy {} to {} -> {}", backup.display(), path, err))?;
+        tracing::warn!("restored {} from {}", path, backup.display());
+    } else {
+        tracing::warn!("starting {} as an empty database", path);
+    }
+    open_db(path).map_err(describe)
+}
+
+// What a torn write or a stray file looks like to persy: a header that isn't persy's, pages
+// cut short, or bytes that don't decode.  A file locked by another process, missing
+// permissions and the like are not damage, and moving the file wouldn't fix them.
+fn damaged(err: &StructsyError) -> bool {
+    match err {
+        StructsyError::PersyError(PersyError::Io { from, .. }) => matches!(
+            from.kind(),
+            std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidData
+        ),
+        StructsyError::PersyError(
+            PersyError::NotPersyFile
+            | PersyError::DecodingUtf8(_)
+            | PersyError::DecodingDataEncoding(_)
+            | PersyError::VarIntError(_),
+        ) => true,
+        _ => false,
+    }
+}
+
+// The newest `POST /v1/admin/backup` copy of `db_path` in `backup_dir`.  The time in the names
+// sorts like the times themselves.
+fn latest_backup(db_path: &str, backup_dir: &str) -> Result<PathBuf, String> {
+    let db_path = Path::new(db_path);
+    let stem = db_path
+        .file_stem()
+        .map_or("db".into(), |stem| stem.to_string_lossy());
+    let ext = db_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let entries = std::fs::read_dir(backup_dir)
+        .map_err(|err| format!("failed to read BACKUP_DIR {} -> {}", backup_dir, err))?;
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                return false;
+            };
+            name.strip_prefix(&format!("{}-", stem))
+                .and_then(|rest| rest.strip_suffix(ext.as_str()))
+                .is_some_and(|time| NaiveDateTime::parse_from_str(time, BACKUP_TIME).is_ok())
+        })
+        .max()
+        .ok_or_else(|| {
+            format!(
+                "DB_AUTO_RECOVER=backup but {} holds no backup of {}",
+                backup_dir,
+                db_path.display()
+            )
+        })
+}
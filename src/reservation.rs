@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use structsy::{derive::Persistent, OwnedSytx, Ref, StructsyTx};
+use utoipa::ToSchema;
+
+use crate::audit::{self, RequestId};
+use crate::beverage::{Beer, Beverage, Coffee, Soda};
+use crate::compact::{self, NewId, Remap};
+use crate::timestamp::Timestamp;
+use crate::{parse_ref, run_blocking, with_tx, AppError, AppState};
+
+// How often `release_expired` looks for reservations to give back.
+const RELEASE_INTERVAL: Duration = Duration::from_secs(5);
+
+// One unit of a drink held for a customer between choosing it and paying.  The unit leaves
+// the stock when reserved; a confirm turns it into a sale, a cancel or the expiry puts it back.
+#[derive(Persistent)]
+pub struct Reservation {
+    kind: String,
+    #[index(mode = "cluster")]
+    ref_id: String,
+    expires_at: Timestamp,
+}
+
+impl Reservation {
+    pub fn new(kind: &str, ref_id: &str, ttl: Duration) -> Self {
+        Reservation {
+            kind: kind.to_owned(),
+            ref_id: ref_id.to_owned(),
+            expires_at: Timestamp::now().after(ttl),
+        }
+    }
+
+    pub fn ref_id(&self) -> &str {
+        &self.ref_id
+    }
+
+    pub fn expires_at(&self) -> Timestamp {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Timestamp::now()
+    }
+}
+
+impl Remap for Reservation {
+    fn remap(&mut self, new_id: NewId) {
+        compact::remap(&self.kind, &mut self.ref_id, new_id);
+    }
+}
+
+// What `POST /<kind>/:id/reserve` answers with.
+#[derive(Serialize, ToSchema)]
+pub struct Reserved {
+    // the reservation, to confirm or cancel
+    pub id: String,
+    // the drink it holds a unit of
+    pub ref_id: String,
+    pub expires_at: Timestamp,
+}
+
+// The `T` reservation `id`, read in `tx`; 404 when there is none, or it is for another type.
+pub fn read<T: Beverage>(
+    tx: &mut OwnedSytx,
+    id: &str,
+) -> Result<(Ref<Reservation>, Reservation), AppError> {
+    let r_id: Ref<Reservation> = parse_ref(id)?;
+    match tx.read(&r_id)? {
+        Some(reservation) if reservation.kind == T::KIND => Ok((r_id, reservation)),
+        _ => Err(AppError::NotFound(format!("reservation {} not found", id))),
+    }
+}
+
+// Delete the reservation and put its unit back into the stock of its drink, if that is still
+// stored.  A restock may have filled the stock to the top meanwhile; the unit is then lost
+// rather than failing every later expiry run.
+pub fn release<T: Beverage>(
+    tx: &mut OwnedSytx,
+    r_id: &Ref<Reservation>,
+    reservation: &Reservation,
+    request_id: &RequestId,
+) -> Result<(), AppError> {
+    tx.delete(r_id)?;
+    let p_id: Ref<T> = parse_ref(&reservation.ref_id)?;
+    if let Some(mut value) = tx.read(&p_id)? {
+        value.set_stock(value.stock().saturating_add(1));
+        value.set_version(value.version() + 1);
+        tx.update(&p_id, &value)?;
+        audit::record(tx, "release", T::KIND, &reservation.ref_id, request_id)?;
+    }
+    Ok(())
+}
+
+// Background task giving the units of expired reservations back, every `RELEASE_INTERVAL`,
+// in each database file, for as long as the server runs.  A reservation past its expiry can
+// no longer be confirmed even before this has caught up with it.
+pub async fn release_expired(state: AppState) {
+    let mut interval = tokio::time::interval(RELEASE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        let released = run_blocking(move || {
+            let mut released = 0;
+            for file in state.db.files() {
+                released += with_tx(file, |tx| {
+                    let expired: Vec<_> = tx
+                        .scan::<Reservation>()?
+                        .filter(|(_, reservation)| reservation.is_expired())
+                        .collect();
+                    let request_id = RequestId::default();
+                    for (r_id, reservation) in &expired {
+                        match reservation.kind.as_str() {
+                            Coffee::KIND => release::<Coffee>(tx, r_id, reservation, &request_id)?,
+                            Beer::KIND => release::<Beer>(tx, r_id, reservation, &request_id)?,
+                            Soda::KIND => release::<Soda>(tx, r_id, reservation, &request_id)?,
+                            _ => tx.delete(r_id)?,
+                        }
+                    }
+                    Ok(expired.len())
+                })?;
+            }
+            Ok(released)
+        })
+        .await;
+        match released {
+            Ok(0) => {}
+            Ok(released) => tracing::info!("released {} expired reservations", released),
+            Err(err) => tracing::error!("failed to release expired reservations -> {}", err),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::State, middleware::from_fn_with_state, routing::get, Router};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use structsy::derive::Persistent;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::compact::{self, NewId, Remap};
+use crate::problem::Problem;
+use crate::timestamp::Timestamp;
+use crate::{auth, run_blocking, AppError, AppJson, AppQuery, AppState};
+
+// One completed purchase, written in the same transaction as the stock decrement.
+#[derive(Serialize, Deserialize, Persistent)]
+pub struct Sale {
+    kind: String,
+    ref_id: String,
+    brand: String,
+    price_cents: u32,
+    sold_at: Timestamp,
+}
+
+impl Sale {
+    pub fn new(kind: &str, ref_id: &str, brand: &str, price_cents: u32) -> Self {
+        Sale {
+            kind: kind.to_owned(),
+            ref_id: ref_id.to_owned(),
+            brand: brand.to_owned(),
+            price_cents,
+            sold_at: Timestamp::now(),
+        }
+    }
+}
+
+impl Remap for Sale {
+    fn remap(&mut self, new_id: NewId) {
+        compact::remap(&self.kind, &mut self.ref_id, new_id);
+    }
+}
+
+// `?date=YYYY-MM-DD`, a UTC day; today when omitted.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DayParams {
+    date: Option<String>,
+}
+
+#[derive(Serialize, Default, ToSchema)]
+struct Totals {
+    count: usize,
+    total_cents: u64,
+}
+
+impl Totals {
+    fn add(&mut self, sale: &Sale) {
+        self.count += 1;
+        self.total_cents += u64::from(sale.price_cents);
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct DailyReport {
+    date: NaiveDate,
+    // per drink type, only the types that sold anything
+    kinds: BTreeMap<String, Totals>,
+    #[serde(flatten)]
+    total: Totals,
+}
+
+// Purchases made on one day, counted and summed per drink type.
+#[utoipa::path(
+    get,
+    path = "/reports/daily",
+    tag = "reports",
+    params(DayParams),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The day's takings", body = DailyReport),
+        (
+            status = 400,
+            description = "A malformed date",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+        (
+            status = 401,
+            description = "Missing or wrong X-API-Key",
+            body = Problem,
+            content_type = "application/problem+json",
+        ),
+    )
+)]
+async fn daily(
+    State(state): State<AppState>,
+    AppQuery(params): AppQuery<DayParams>,
+) -> Result<AppJson<DailyReport>, AppError> {
+    let date = match params.date.as_deref().map(str::trim) {
+        None | Some("") => Utc::now().date_naive(),
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+            AppError::BadRequest(format!("date must look like 2024-03-05, got {:?}", date))
+        })?,
+    };
+    run_blocking(move || {
+        let mut report = DailyReport {
+            date,
+            kinds: BTreeMap::new(),
+            total: Totals::default(),
+        };
+        // with DB_FILES=per-kind each type's sales are in its own file
+        for file in state.db.files() {
+            for (_, sale) in file.connection().scan::<Sale>()? {
+                if sale.sold_at.to_datetime().date_naive() != date {
+                    continue;
+                }
+                report.total.add(&sale);
+                report
+                    .kinds
+                    .entry(sale.kind.clone())
+                    .or_default()
+                    .add(&sale);
+            }
+        }
+        Ok(AppJson(report))
+    })
+    .await
+}
+
+// Takings are for the operator, so reports need the API key.
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/reports/daily", get(daily))
+        .route_layer(from_fn_with_state(state.clone(), auth::require_api_key))
+        .with_state(state)
+}
@@ -0,0 +1,47 @@
+use serde_json::json;
+
+use crate::audit::RequestId;
+use crate::beverage::{Beer, Beverage, Coffee, Soda};
+use crate::db::{Databases, DbFile};
+use crate::export::{self, Export, ImportMode};
+use crate::AppError;
+
+// Sample drinks for development, stored in the `export` format and loaded like an import.
+fn sample() -> Export {
+    serde_json::from_value(json!({
+        "coffees": [
+            {"coffee": {"brand": "Lavazza", "machine_id": "lobby", "size": 250,
+                        "stock": 12, "price_cents": 180}},
+            {"coffee": {"brand": "Illy", "machine_id": "lobby", "size": 200,
+                        "stock": 8, "price_cents": 220}},
+            {"coffee": {"brand": "Juan Valdez", "machine_id": "floor-2", "size": 350,
+                        "stock": 2, "price_cents": 250}},
+        ],
+        "beers": [
+            {"beer": {"brand": "Club Colombia", "machine_id": "lobby", "size": 330,
+                      "stock": 24, "price_cents": 300}},
+            {"beer": {"brand": "Aguila", "machine_id": "floor-2", "size": 330,
+                      "stock": 18, "price_cents": 250}},
+            {"beer": {"brand": "Poker", "machine_id": "floor-2", "size": 500,
+                      "stock": 0, "price_cents": 350}},
+        ],
+    }))
+    .expect("the sample document matches `Export`")
+}
+
+fn is_empty<T: Beverage>(file: &DbFile) -> Result<bool, AppError> {
+    Ok(file.connection().scan::<T>()?.next().is_none())
+}
+
+// Load the sample drinks, but only into a store with no drink of any kind, deleted ones
+// included.  Returns whether anything was inserted.
+pub fn seed(db: &Databases) -> Result<bool, AppError> {
+    if !(is_empty::<Coffee>(db.of::<Coffee>())?
+        && is_empty::<Beer>(db.of::<Beer>())?
+        && is_empty::<Soda>(db.of::<Soda>())?)
+    {
+        return Ok(false);
+    }
+    export::load(db, sample(), ImportMode::Append, &RequestId::default())?;
+    Ok(true)
+}
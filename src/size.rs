@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use structsy::derive::PersistentEmbedded;
+use utoipa::ToSchema;
+
+// Largest sizes, in millilitres, of the smaller two categories.
+const SMALL_MAX: u32 = 250;
+const MEDIUM_MAX: u32 = 400;
+
+// The size class a machine slot takes, derived from the numeric `size` so records stored
+// before it existed fit in too.
+#[derive(
+    Serialize, Deserialize, PersistentEmbedded, ToSchema, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeCategory {
+    // up to 250 ml
+    Small,
+    // up to 400 ml
+    Medium,
+    // anything bigger
+    Large,
+}
+
+impl SizeCategory {
+    pub fn of(size: u32) -> Self {
+        if size <= SMALL_MAX {
+            SizeCategory::Small
+        } else if size <= MEDIUM_MAX {
+            SizeCategory::Medium
+        } else {
+            SizeCategory::Large
+        }
+    }
+}
+
+impl std::fmt::Display for SizeCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SizeCategory::Small => "small",
+            SizeCategory::Medium => "medium",
+            SizeCategory::Large => "large",
+        })
+    }
+}
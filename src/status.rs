@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::beverage::{Beer, Beverage, Coffee, Soda};
+use crate::db::DbFile;
+use crate::{run_blocking, AppError, AppJson, AppState};
+
+// Process-wide counters behind `GET /status`.
+pub struct Status {
+    started: Instant,
+    requests: AtomicU64,
+    // set by `serve` while it takes traffic, for `/ready`
+    ready: AtomicBool,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Status {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+}
+
+// Counts every request the server answers, probes included.
+pub async fn count_request(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    state.status.requests.fetch_add(1, Ordering::Relaxed);
+    next.run(req).await
+}
+
+#[derive(Serialize, ToSchema)]
+struct Records {
+    coffees: usize,
+    beers: usize,
+    sodas: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Report {
+    uptime_secs: u64,
+    requests: u64,
+    // live records per type; soft-deleted ones aren't counted
+    records: Records,
+}
+
+fn live<T: Beverage>(file: &DbFile) -> Result<usize, AppError> {
+    Ok(file
+        .connection()
+        .scan::<T>()?
+        .filter(|(_, value)| !value.is_deleted())
+        .count())
+}
+
+// Richer than `/health`: how long the server has been up, how busy it was and what it holds.
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "meta",
+    responses((status = 200, description = "Uptime, traffic and record counts", body = Report))
+)]
+pub async fn status(State(state): State<AppState>) -> Result<AppJson<Report>, AppError> {
+    let uptime_secs = state.status.started.elapsed().as_secs();
+    let requests = state.status.requests.load(Ordering::Relaxed);
+    let records = run_blocking(move || {
+        let db = &state.db;
+        Ok(Records {
+            coffees: live::<Coffee>(db.of::<Coffee>())?,
+            beers: live::<Beer>(db.of::<Beer>())?,
+            sodas: live::<Soda>(db.of::<Soda>())?,
+        })
+    })
+    .await?;
+    Ok(AppJson(Report {
+        uptime_secs,
+        requests,
+        records,
+    }))
+}
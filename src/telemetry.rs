@@ -0,0 +1,43 @@
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+// Spans shipped to an OpenTelemetry collector over OTLP/gRPC, batched in the background.
+// Dropping this without `shutdown` loses the last batch.
+pub struct Telemetry(TracerProvider);
+
+impl Telemetry {
+    // Exporter for the collector at `endpoint`, e.g. `http://localhost:4317`.  The service
+    // is named after the crate unless OTEL_SERVICE_NAME says otherwise.
+    pub fn new(endpoint: &str) -> Result<Self, opentelemetry::trace::TraceError> {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let service = std::env::var("OTEL_SERVICE_NAME")
+            .unwrap_or_else(|_| env!("CARGO_PKG_NAME").to_owned());
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", service)]))
+            .build();
+        Ok(Telemetry(provider))
+    }
+
+    // Turns the `tracing` spans, fields such as `request_id` included, into OpenTelemetry
+    // ones.  Sits next to the console layer rather than replacing it.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.0.tracer(env!("CARGO_PKG_NAME")))
+    }
+
+    // Export what is still buffered; call once the server has stopped.
+    pub fn shutdown(self) {
+        if let Err(err) = self.0.shutdown() {
+            tracing::warn!("failed to flush traces on shutdown -> {}", err);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use structsy::derive::PersistentEmbedded;
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, Schema, SchemaFormat, Type};
+use utoipa::openapi::RefOr;
+
+// A point in time, persisted as milliseconds since the Unix epoch and exchanged on the wire as
+// an RFC3339 string.  Structsy has no native date type, hence the wrapper.
+#[derive(PersistentEmbedded, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    millis: i64,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Utc::now().into()
+    }
+
+    pub fn to_datetime(self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.millis).unwrap_or_default()
+    }
+
+    pub fn older_than(self, age: std::time::Duration) -> bool {
+        let age = chrono::Duration::from_std(age).unwrap_or(chrono::Duration::MAX);
+        Utc::now().signed_duration_since(self.to_datetime()) > age
+    }
+
+    // `delay` later than `self`.
+    pub fn after(self, delay: std::time::Duration) -> Self {
+        let delay = i64::try_from(delay.as_millis()).unwrap_or(i64::MAX);
+        Timestamp {
+            millis: self.millis.saturating_add(delay),
+        }
+    }
+
+    // Strict RFC3339, for timestamps supplied by clients.
+    pub fn parse(value: &str) -> Option<Self> {
+        DateTime::parse_from_rfc3339(value.trim())
+            .ok()
+            .map(|time| time.with_timezone(&Utc).into())
+    }
+
+    // The IMF-fixdate of `Last-Modified` and friends, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+    // Whole seconds only, the milliseconds are dropped.
+    pub fn to_http_date(self) -> String {
+        self.to_datetime()
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    }
+
+    pub fn parse_http_date(value: &str) -> Option<Self> {
+        DateTime::parse_from_rfc2822(value.trim())
+            .ok()
+            .map(|time| time.with_timezone(&Utc).into())
+    }
+
+    // The same instant cut down to a whole second, as it reads in an HTTP date.
+    pub fn whole_seconds(self) -> Self {
+        Timestamp {
+            millis: self.millis.div_euclid(1000) * 1000,
+        }
+    }
+
+    // Best-effort conversion of the free-form strings stored before `time` had a type: RFC3339
+    // first, then a naive `YYYY-MM-DD HH:MM:SS` taken as UTC, and the epoch when neither fits.
+    pub fn parse_lenient(value: &str) -> Self {
+        let value = value.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            return time.with_timezone(&Utc).into();
+        }
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+            return time.and_utc().into();
+        }
+        DateTime::<Utc>::UNIX_EPOCH.into()
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(time: DateTime<Utc>) -> Self {
+        Timestamp {
+            millis: time.timestamp_millis(),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_datetime().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        DateTime::<Utc>::deserialize(deserializer).map(Timestamp::from)
+    }
+}
+
+// Documented as what it is on the wire, not as the stored millis.
+impl utoipa::PartialSchema for Timestamp {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+            .into()
+    }
+}
+
+impl utoipa::ToSchema for Timestamp {}
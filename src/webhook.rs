@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::Serialize;
+use structsy::derive::Persistent;
+use structsy::StructsyTx;
+
+use crate::compact::{self, NewId, Remap};
+use crate::db::DbFile;
+use crate::timestamp::Timestamp;
+use crate::{run_blocking, with_tx};
+
+// Per attempt, connecting included.
+const TIMEOUT: Duration = Duration::from_secs(3);
+const ATTEMPTS: u32 = 6;
+// Doubled after every failed attempt, with up to half of it taken off at random so receivers
+// coming back up don't get every sender's retry at the same instant.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+// Longest a delivery keeps retrying, waits included; it gives up early rather than sleep past
+// this.
+const MAX_RETRY_TIME: Duration = Duration::from_secs(30);
+
+// A sale, as POSTed to WEBHOOK_URL.
+#[derive(Serialize)]
+pub struct SaleEvent {
+    pub kind: &'static str,
+    pub id: String,
+    pub brand: String,
+    pub price_cents: u32,
+}
+
+// An event the receiver still refused after every retry, kept for the next startup.
+#[derive(Persistent)]
+pub struct PendingWebhook {
+    kind: String,
+    ref_id: String,
+    // the JSON as it would have been POSTed
+    body: String,
+    failed_at: Timestamp,
+}
+
+impl Remap for PendingWebhook {
+    fn remap(&mut self, new_id: NewId) {
+        compact::remap(&self.kind, &mut self.ref_id, new_id);
+        let Ok(mut body) = serde_json::from_str::<serde_json::Value>(&self.body) else {
+            return;
+        };
+        if let Some(id) = body.get_mut("id") {
+            *id = self.ref_id.clone().into();
+            self.body = body.to_string();
+        }
+    }
+}
+
+// Tells an external system about sales as they happen.  Delivery runs in the background and
+// retries with backoff; what still fails is stored as a `PendingWebhook` and sent again by
+// `drain_pending` when the server next starts.
+pub struct Webhook {
+    client: reqwest::Client,
+    url: Arc<str>,
+    // DB_PATH, where the undelivered events are kept
+    file: Arc<DbFile>,
+}
+
+impl Webhook {
+    pub fn new(url: &str, file: Arc<DbFile>) -> Result<Self, reqwest::Error> {
+        Ok(Webhook {
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            url: url.into(),
+            file,
+        })
+    }
+
+    // Returns at once; the caller's response never waits on the receiver.
+    pub fn notify(&self, event: SaleEvent) {
+        let body = match serde_json::to_string(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!("failed to encode the webhook for {} -> {}", event.id, err);
+                return;
+            }
+        };
+        let (client, url) = (self.client.clone(), self.url.clone());
+        let file = self.file.clone();
+        tokio::spawn(async move {
+            if let Err(err) = deliver(&client, &url, &body).await {
+                tracing::error!(
+                    "webhook for {} {} not delivered, keeping it for the next start -> {}",
+                    event.kind,
+                    event.id,
+                    err
+                );
+                let pending = PendingWebhook {
+                    kind: event.kind.to_owned(),
+                    ref_id: event.id,
+                    body,
+                    failed_at: Timestamp::now(),
+                };
+                let kept = run_blocking(move || {
+                    with_tx(&file, |tx| {
+                        tx.insert(&pending)?;
+                        Ok(())
+                    })
+                })
+                .await;
+                if let Err(err) = kept {
+                    tracing::error!("failed to keep the undelivered webhook -> {}", err);
+                }
+            }
+        });
+    }
+
+    // Send the events earlier runs could not, one after the other, in the background.  Each
+    // is deleted once delivered; one failing again stays for the start after.
+    pub fn drain_pending(&self) {
+        let (client, url) = (self.client.clone(), self.url.clone());
+        let file = self.file.clone();
+        tokio::spawn(async move {
+            let scan = file.connection();
+            let pending = run_blocking(move || Ok(scan.scan::<PendingWebhook>()?.collect()))
+                .await
+                .unwrap_or_else(|err| {
+                    tracing::error!("failed to read the undelivered webhooks -> {}", err);
+                    Vec::new()
+                });
+            if !pending.is_empty() {
+                tracing::info!("retrying {} undelivered webhooks", pending.len());
+            }
+            for (id, event) in pending {
+                if let Err(err) = deliver(&client, &url, &event.body).await {
+                    tracing::error!(
+                        "webhook for {} {} from {} still not delivered -> {}",
+                        event.kind,
+                        event.ref_id,
+                        event.failed_at.to_datetime(),
+                        err
+                    );
+                    continue;
+                }
+                let file = file.clone();
+                let deleted = run_blocking(move || {
+                    with_tx(&file, |tx| {
+                        // a compaction since the scan gives the same id to another event
+                        if tx.read(&id)?.is_some_and(|kept| kept.body == event.body) {
+                            tx.delete(&id)?;
+                        }
+                        Ok(())
+                    })
+                })
+                .await;
+                if let Err(err) = deleted {
+                    tracing::error!("failed to drop a delivered webhook -> {}", err);
+                }
+            }
+        });
+    }
+}
+
+// POST `body` until the receiver takes it, at most `ATTEMPTS` times and for `MAX_RETRY_TIME`;
+// the last error when it never does.
+async fn deliver(client: &reqwest::Client, url: &str, body: &str) -> Result<(), reqwest::Error> {
+    let started = Instant::now();
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let sent = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_owned())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        let err = match sent {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        let wait = delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        if attempt == ATTEMPTS || started.elapsed() + wait > MAX_RETRY_TIME {
+            return Err(err);
+        }
+        tracing::warn!(
+            "webhook attempt {} failed, retrying in {:?} -> {}",
+            attempt,
+            wait,
+            err
+        );
+        tokio::time::sleep(wait).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,187 @@
+// The operator's maintenance and admin routes.
+mod common;
+
+use std::path::Path;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::json;
+
+use common::{app_with, call, coffee, config, create_coffee, send, API_KEY};
+use vending_structsy::config::{Config, DbFiles};
+use vending_structsy::{build_router, db::Databases, AppStateT};
+
+// A store in files of its own under the temp dir, none left from an earlier run.
+fn on_disk(name: &str, db_files: DbFiles) -> Config {
+    let dir = std::env::temp_dir().join(format!("vending-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut config = config();
+    config.db_path = dir.join("track.db").display().to_string();
+    config.db_files = db_files;
+    config
+}
+
+// Opened as at startup; dropping it closes the files again.
+fn open(config: &Config) -> Router {
+    let db = Databases::open(config).expect("the stores open");
+    build_router(AppStateT::new(db, config.clone(), None))
+}
+
+fn remove(config: &Config) {
+    let dir = Path::new(&config.db_path).parent().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn compact_deletes_expired_idempotency_keys() {
+    let mut config = on_disk("purge", DbFiles::Shared);
+    config.idempotency_ttl = Duration::ZERO;
+    let app = open(&config);
+    let create = Request::post("/v1/coffee/create")
+        .header("content-type", "application/json")
+        .header("x-api-key", API_KEY)
+        .header("idempotency-key", "one")
+        .body(Body::from(coffee("Lavazza").to_string()))
+        .unwrap();
+    assert_eq!(call(&app, create).await.status(), StatusCode::CREATED);
+
+    let (status, _, compacted) = send(&app, Method::POST, "/v1/admin/compact", None).await;
+    assert_eq!(status, StatusCode::OK, "{}", compacted);
+    assert_eq!(compacted["idempotency_keys"], 1);
+    assert_eq!(compacted["rebuilt"], false);
+    assert!(compacted["before_bytes"].as_u64().unwrap() > 0);
+    assert!(compacted["after_bytes"].as_u64().unwrap() > 0);
+    let (_, _, compacted) = send(&app, Method::POST, "/v1/admin/compact", None).await;
+    assert_eq!(compacted["idempotency_keys"], 0);
+    drop(app);
+    remove(&config);
+
+    let (status, _, problem) = send(&common::app(), Method::POST, "/v1/admin/compact", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        problem["detail"],
+        "the database is in memory, there is no file to compact"
+    );
+}
+
+// With a file per type, so the cart in DB_PATH has to follow its coffee into the other file.
+#[tokio::test]
+async fn rebuild_keeps_every_record_and_reference() {
+    let config = on_disk("rebuild", DbFiles::PerKind);
+    let app = open(&config);
+    let mut ids = Vec::new();
+    for brand in ["Illy", "Lavazza", "Segafredo"] {
+        ids.push(create_coffee(&app, &coffee(brand)).await);
+    }
+    // the gaps a compaction is for
+    for id in [&ids[0], &ids[1]] {
+        send(
+            &app,
+            Method::DELETE,
+            &format!("/v1/coffee/delete/{}", id),
+            None,
+        )
+        .await;
+    }
+    let purchase = format!("/v1/coffee/{}/purchase", ids[2]);
+    let paid = json!({ "amount_cents": 250 });
+    let (status, _, _) = send(&app, Method::POST, &purchase, Some(&paid)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, _, cart) = send(&app, Method::POST, "/v1/cart", None).await;
+    let item = json!({ "kind": "coffee", "id": ids[2], "quantity": 1 });
+    let items = format!("/v1/cart/{}/items", cart["id"].as_str().unwrap());
+    send(&app, Method::POST, &items, Some(&item)).await;
+
+    let uri = "/v1/admin/compact?rebuild=true";
+    let (status, _, compacted) = send(&app, Method::POST, uri, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", compacted);
+    assert_eq!(compacted["rebuilt"], true);
+    assert!(compacted["after_bytes"].as_u64().unwrap() > 0);
+
+    let (_, _, list) = send(
+        &app,
+        Method::GET,
+        "/v1/coffee/list?include_deleted=true",
+        None,
+    )
+    .await;
+    assert_eq!(list["total"], 3);
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 1);
+    let kept = &list["coffees"][0];
+    assert_eq!(kept["coffee"]["brand"], "Segafredo");
+    assert_eq!(kept["coffee"]["stock"], 4);
+    let (_, _, report) = send(&app, Method::GET, "/v1/reports/daily", None).await;
+    assert_eq!(report["count"], 1);
+    let (_, _, audit) = send(&app, Method::GET, "/v1/audit", None).await;
+    let purchased = audit["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["op"] == "purchase")
+        .unwrap();
+    assert_eq!(purchased["ref_id"], kept["id"]);
+
+    // the cart still checks out, from the new files
+    let (checkout, paid) = (
+        items.replace("items", "checkout"),
+        json!({ "amount_cents": 250 }),
+    );
+    let (status, _, receipt) = send(&app, Method::POST, &checkout, Some(&paid)).await;
+    assert_eq!(status, StatusCode::OK, "{}", receipt);
+    let (_, _, fetched) = send(
+        &app,
+        Method::GET,
+        &format!("/v1/coffee/{}", kept["id"].as_str().unwrap()),
+        None,
+    )
+    .await;
+    assert_eq!(fetched["coffee"]["stock"], 3);
+
+    // and what is written now is still there after a restart
+    drop(app);
+    let app = open(&config);
+    let (_, _, report) = send(&app, Method::GET, "/v1/reports/daily", None).await;
+    assert_eq!(report["count"], 2);
+    drop(app);
+    remove(&config);
+}
+
+// An admin page on another origin switches the mode with a PUT.
+#[tokio::test]
+async fn maintenance_is_switched_across_origins() {
+    let app = app_with(config());
+    let preflight = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/v1/maintenance")
+        .header(header::ORIGIN, "http://admin.example")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .body(Body::empty())
+        .unwrap();
+    let response = call(&app, preflight).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let allowed = response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS]
+        .to_str()
+        .unwrap();
+    assert!(
+        allowed.split(',').any(|method| method.trim() == "PUT"),
+        "{}",
+        allowed
+    );
+
+    let on = json!({ "enabled": true, "retry_after_secs": 5 });
+    let (status, _, _) = send(&app, Method::PUT, "/v1/maintenance", Some(&on)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, headers, _) = send(
+        &app,
+        Method::POST,
+        "/v1/coffee/create",
+        Some(&coffee("Illy")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(headers[header::RETRY_AFTER], "5");
+}
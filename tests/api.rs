@@ -0,0 +1,479 @@
+// The CRUD routes end to end, and the error paths every client runs into.
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use serde_json::json;
+use structsy::Structsy;
+
+use common::{app, app_with, call, coffee, config, create_coffee, json, request, send};
+use vending_structsy::{build_router, db::Databases, AppStateT, MEMORY_DB};
+
+#[tokio::test]
+async fn create_list_fetch_update_delete() {
+    let app = app();
+    let id = create_coffee(&app, &coffee("Lavazza")).await;
+
+    let (status, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list["total"], 1);
+    assert_eq!(list["coffees"][0]["id"], id);
+
+    let uri = format!("/v1/coffee/{}", id);
+    let (status, _, fetched) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched["coffee"]["brand"], "Lavazza");
+
+    let mut changed = fetched["coffee"].clone();
+    changed["price_cents"] = json!(300);
+    let update = format!("/v1/coffee/update/{}", id);
+    let (status, _, _) = send(&app, Method::POST, &update, Some(&changed)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, _, fetched) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(fetched["coffee"]["price_cents"], 300);
+    assert_eq!(fetched["coffee"]["version"], 2);
+
+    let delete = format!("/v1/coffee/delete/{}", id);
+    let (status, _, _) = send(&app, Method::DELETE, &delete, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 0);
+}
+
+// The paths from before `/v1` answer the same, with a `Warning` naming the versioned one.
+#[tokio::test]
+async fn unversioned_path_is_deprecated() {
+    let app = app();
+    let id = create_coffee(&app, &coffee("Lavazza")).await;
+    let (status, headers, old) = send(&app, Method::GET, "/coffee/list", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let warning = headers[header::WARNING].to_str().unwrap();
+    assert!(warning.ends_with("use /v1/coffee/list\""), "{}", warning);
+    assert_eq!(old["coffees"][0]["id"], id);
+
+    let (_, headers, new) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert!(headers.get(header::WARNING).is_none());
+    assert_eq!(new, old);
+}
+
+#[tokio::test]
+async fn missing_record_is_a_404() {
+    let app = app();
+    let id = create_coffee(&app, &coffee("Illy")).await;
+    let delete = format!("/v1/coffee/delete/{}", id);
+    send(&app, Method::DELETE, &delete, None).await;
+    let (status, _, problem) = send(&app, Method::DELETE, &delete, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(problem["code"], "NOT_FOUND");
+}
+
+#[tokio::test]
+async fn update_with_malformed_id_is_a_400() {
+    let mut body = coffee("Lavazza");
+    body["version"] = json!(1);
+    let uri = "/v1/coffee/update/not-a-real-ref";
+    let (status, headers, problem) = send(&app(), Method::POST, uri, Some(&body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(headers["content-type"], "application/problem+json");
+    assert_eq!(problem["code"], "INVALID_ID");
+    assert_eq!(problem["detail"], "invalid id not-a-real-ref");
+}
+
+// The schema is defined once, by `open_db`; the create handlers don't define their type.
+#[tokio::test]
+async fn create_relies_on_the_startup_schema() {
+    let app = app();
+    let mut soda = coffee("Fanta");
+    soda["carbonated"] = json!(true);
+    for (uri, body) in [
+        ("/v1/coffee/create", coffee("Lavazza")),
+        ("/v1/beer/create", coffee("Guinness")),
+        ("/v1/soda/create", soda),
+    ] {
+        let (status, _, created) = send(&app, Method::POST, uri, Some(&body)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}: {}", uri, created);
+    }
+
+    // without it the same create fails
+    let connection = Structsy::memory().unwrap();
+    let bare = build_router(AppStateT::new(
+        Databases::single(MEMORY_DB, connection),
+        config(),
+        None,
+    ));
+    let body = coffee("Lavazza");
+    let (status, _, _) = send(&bare, Method::POST, "/v1/coffee/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn empty_brand_and_zero_size_are_listed() {
+    let app = app();
+    let uri = "/v1/coffee/create";
+
+    let (status, _, problem) = send(&app, Method::POST, uri, Some(&coffee("  "))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem["detail"], "validation failed");
+    assert_eq!(problem["errors"], json!(["brand must not be empty"]));
+
+    let mut body = coffee("Lavazza");
+    body["size"] = json!(0);
+    let (status, _, problem) = send(&app, Method::POST, uri, Some(&body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem["errors"], json!(["size must be greater than 0"]));
+
+    body["brand"] = json!("");
+    let (status, _, problem) = send(&app, Method::POST, uri, Some(&body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        problem["errors"],
+        json!(["brand must not be empty", "size must be greater than 0"])
+    );
+
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 0);
+}
+
+#[tokio::test]
+async fn records_are_filtered_by_machine() {
+    let app = app();
+    let mut body = coffee("Lavazza");
+    body["machine_id"] = json!("  ");
+    let (status, _, problem) = send(&app, Method::POST, "/v1/coffee/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem["errors"], json!(["machine_id must not be empty"]));
+    body.as_object_mut().unwrap().remove("machine_id");
+    let (status, _, _) = send(&app, Method::POST, "/v1/coffee/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    create_coffee(&app, &coffee("Lavazza")).await;
+    let mut other = coffee("Illy");
+    other["machine_id"] = json!("m-2");
+    create_coffee(&app, &other).await;
+    for (query, count) in [
+        ("", 2),
+        ("?machine_id=m-1", 1),
+        ("?machine_id=m-2", 1),
+        ("?machine_id=m-2&brand=lavazza", 0),
+        ("?machine_id=M-1", 0),
+    ] {
+        let uri = format!("/v1/coffee/count{}", query);
+        let (_, _, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(body["count"], count, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn category_is_derived_from_the_size() {
+    let app = app();
+    let mut body = coffee("Lavazza");
+    body["category"] = json!("large");
+    let (status, _, problem) = send(&app, Method::POST, "/v1/coffee/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        problem["errors"],
+        json!(["category large does not match size 200, which is small"])
+    );
+
+    // left out, the server fills it in
+    let id = create_coffee(&app, &coffee("Lavazza")).await;
+    let uri = format!("/v1/coffee/{}", id);
+    let (_, _, fetched) = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(fetched["coffee"]["category"], "small");
+}
+
+#[tokio::test]
+async fn patch_changes_only_the_given_fields() {
+    let app = app();
+    let id = create_coffee(&app, &coffee("Lavazza")).await;
+    let uri = format!("/v1/coffee/{}", id);
+    let patch = json!({ "size": 150, "version": 1 });
+    let (status, _, patched) = send(&app, Method::PATCH, &uri, Some(&patch)).await;
+    assert_eq!(status, StatusCode::OK, "{}", patched);
+    assert_eq!(patched["coffee"]["size"], 150);
+    assert_eq!(patched["coffee"]["brand"], "Lavazza");
+
+    let (_, _, fetched) = send(&app, Method::GET, &uri, None).await;
+    let mut expected = coffee("Lavazza");
+    expected["size"] = json!(150);
+    for (field, value) in expected.as_object().unwrap() {
+        assert_eq!(&fetched["coffee"][field], value, "{}", field);
+    }
+    assert_eq!(fetched["coffee"]["version"], 2);
+
+    // the version it was sent is stale now
+    let (status, _, problem) = send(&app, Method::PATCH, &uri, Some(&patch)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let detail = format!("coffee {} is at version 2, not 1", id);
+    assert_eq!(problem["detail"], detail);
+}
+
+#[tokio::test]
+async fn oversized_body_is_a_413() {
+    let mut config = config();
+    config.body_limit = 64;
+    let app = app_with(config);
+    let body = coffee(&"x".repeat(100));
+    let (status, headers, problem) =
+        send(&app, Method::POST, "/v1/coffee/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(headers["content-type"], "application/problem+json");
+    assert_eq!(problem["status"], 413);
+    assert_eq!(problem["code"], "INVALID_BODY");
+    assert_eq!(
+        problem["detail"],
+        "Failed to buffer the request body: length limit exceeded"
+    );
+
+    // the same with the length declared up front, as real clients send it
+    let mut request = request(Method::POST, "/v1/coffee/create", Some(&body));
+    let length = body.to_string().len().to_string();
+    request
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, length.parse().unwrap());
+    let response = call(&app, request).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(json(response.into_body()).await["code"], "INVALID_BODY");
+
+    let small = json!({ "brand": "Illy", "machine_id": "m-1", "size": 200 });
+    let (status, _, _) = send(&app, Method::POST, "/v1/coffee/create", Some(&small)).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn non_json_body_is_a_415() {
+    let app = app();
+    let body = coffee("Lavazza");
+    for content_type in [Some("text/plain"), None] {
+        let mut request = request(Method::POST, "/v1/coffee/create", Some(&body));
+        match content_type {
+            Some(value) => request
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, value.parse().unwrap()),
+            None => request.headers_mut().remove(header::CONTENT_TYPE),
+        };
+        let response = call(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let problem = json(response.into_body()).await;
+        assert_eq!(problem["code"], "UNSUPPORTED_MEDIA_TYPE");
+        assert_eq!(
+            problem["detail"],
+            "the body must be sent with Content-Type: application/json"
+        );
+    }
+
+    // a charset parameter is still JSON
+    let mut request = request(Method::POST, "/v1/coffee/create", Some(&body));
+    request.headers_mut().insert(
+        header::CONTENT_TYPE,
+        "application/json; charset=utf-8".parse().unwrap(),
+    );
+    assert_eq!(call(&app, request).await.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn unknown_field_is_a_422() {
+    let app = app();
+    let mut body = coffee("Lavazza");
+    body["sze"] = json!(10);
+    let (status, _, problem) = send(&app, Method::POST, "/v1/coffee/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem["code"], "INVALID_BODY");
+    let detail = problem["detail"].as_str().unwrap();
+    assert!(detail.contains("unknown field `sze`"), "{}", detail);
+
+    // the PATCH body too, next to its `version`
+    let id = create_coffee(&app, &coffee("Illy")).await;
+    let patch = json!({ "sze": 10, "version": 1 });
+    let uri = format!("/v1/coffee/{}", id);
+    let (status, _, problem) = send(&app, Method::PATCH, &uri, Some(&patch)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let detail = problem["detail"].as_str().unwrap();
+    assert!(detail.contains("unknown field `sze`"), "{}", detail);
+}
+
+// Creates are stamped with the current time, so a drink can only be entered twice at the same
+// instant in one batch or through an import of stored records.
+#[tokio::test]
+async fn same_brand_and_time_twice_is_a_409() {
+    let app = app();
+    let batch = json!([coffee("Lavazza"), coffee("Lavazza")]);
+    let (status, _, problem) = send(&app, Method::POST, "/v1/coffee/batch", Some(&batch)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(problem["code"], "CONFLICT");
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 0);
+
+    create_coffee(&app, &coffee("Lavazza")).await;
+    let (_, _, export) = send(&app, Method::GET, "/v1/export", None).await;
+    let (status, _, problem) = send(&app, Method::POST, "/v1/import", Some(&export)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let detail = problem["detail"].as_str().unwrap();
+    assert!(
+        detail.starts_with("a coffee of brand \"Lavazza\" with time "),
+        "{}",
+        detail
+    );
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 1);
+}
+
+// Re-importing an export over the store it came from is what `replace` is for; appending it
+// again runs into the records already there.
+#[tokio::test]
+async fn export_imports_back_with_replace() {
+    let app = app();
+    create_coffee(&app, &coffee("Lavazza")).await;
+    create_coffee(&app, &coffee("Illy")).await;
+    let (_, _, export) = send(&app, Method::GET, "/v1/export", None).await;
+
+    let uri = "/v1/import?mode=replace";
+    let (status, _, imported) = send(&app, Method::POST, uri, Some(&export)).await;
+    assert_eq!(status, StatusCode::OK, "{}", imported);
+    assert_eq!(imported, json!({ "coffees": 2, "beers": 0, "sodas": 0 }));
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 2);
+
+    let (status, _, problem) = send(&app, Method::POST, "/v1/import", Some(&export)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(problem["code"], "CONFLICT");
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 2);
+}
+
+#[tokio::test]
+async fn trailing_slash_is_the_same_route() {
+    let app = app();
+    let id = create_coffee(&app, &coffee("Lavazza")).await;
+    for uri in ["/v1/coffee/list", &format!("/v1/coffee/{}", id), "/health"] {
+        let (status, _, body) = send(&app, Method::GET, uri, None).await;
+        let slashed = format!("{}/", uri);
+        let (slashed_status, _, slashed_body) = send(&app, Method::GET, &slashed, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(slashed_status, status, "{}", slashed);
+        assert_eq!(slashed_body, body, "{}", slashed);
+    }
+
+    let (status, _, _) = send(
+        &app,
+        Method::POST,
+        "/v1/coffee/create/",
+        Some(&coffee("Illy")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn list_pages_by_the_configured_sizes() {
+    let mut config = config();
+    config.default_page_size = 2;
+    config.max_page_size = 5;
+    let app = app_with(config);
+    for brand in ["Illy", "Lavazza", "Segafredo"] {
+        create_coffee(&app, &coffee(brand)).await;
+    }
+
+    let (status, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list["coffees"].as_array().unwrap().len(), 2);
+    assert_eq!(list["total"], 3);
+    assert_eq!(list["meta"]["limit"], 2);
+    assert_eq!(list["meta"]["next"], "/v1/coffee/list?limit=2&offset=2");
+
+    let uri = "/v1/coffee/list?limit=5";
+    let (status, _, list) = send(&app, Method::GET, uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list["coffees"].as_array().unwrap().len(), 3);
+
+    let uri = "/v1/coffee/list?limit=6";
+    let (status, _, problem) = send(&app, Method::GET, uri, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(problem["code"], "BAD_REQUEST");
+    assert_eq!(
+        problem["detail"],
+        "limit 6 is over the maximum page size of 5"
+    );
+}
+
+#[tokio::test]
+async fn reprice_skips_deleted_records() {
+    let app = app();
+    let id = create_coffee(&app, &coffee("Illy")).await;
+    send(
+        &app,
+        Method::DELETE,
+        &format!("/v1/coffee/delete/{}", id),
+        None,
+    )
+    .await;
+    let change = json!({"brand": "Illy", "percent": 10.0});
+    let (status, _, repriced) = send(&app, Method::POST, "/v1/coffee/prices", Some(&change)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(repriced["updated"], 0);
+    assert_eq!(repriced["max_price_cents"], json!(null));
+
+    let live = create_coffee(&app, &coffee("Illy")).await;
+    let (_, _, repriced) = send(&app, Method::POST, "/v1/coffee/prices", Some(&change)).await;
+    assert_eq!(repriced["updated"], 1);
+    assert_eq!(repriced["min_price_cents"], 275);
+    let (_, _, fetched) = send(&app, Method::GET, &format!("/v1/coffee/{}", live), None).await;
+    assert_eq!(fetched["coffee"]["price_cents"], 275);
+}
+
+#[tokio::test]
+async fn dry_run_writes_nothing() {
+    let app = app();
+    let body = coffee("Lavazza");
+    let uri = "/v1/coffee/create?dry_run=true";
+    let (status, _, value) = send(&app, Method::POST, uri, Some(&body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["brand"], "Lavazza");
+    let (_, _, list) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(list["total"], 0);
+}
+
+// A frontend on another origin may send an Idempotency-Key and read the headers answering it.
+#[tokio::test]
+async fn idempotent_create_works_across_origins() {
+    let app = app();
+    let preflight = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/v1/coffee/create")
+        .header(header::ORIGIN, "http://frontend.example")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "idempotency-key")
+        .body(Body::empty())
+        .unwrap();
+    let response = call(&app, preflight).await;
+    let allowed = &response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS];
+    assert!(allowed.to_str().unwrap().contains("idempotency-key"));
+
+    let body = coffee("Lavazza");
+    let mut ids = Vec::new();
+    for replayed in [false, true] {
+        let mut request = request(Method::POST, "/v1/coffee/create", Some(&body));
+        let headers = request.headers_mut();
+        headers.insert(header::ORIGIN, "http://frontend.example".parse().unwrap());
+        headers.insert("idempotency-key", "one".parse().unwrap());
+        let response = call(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let headers = response.headers();
+        assert_eq!(headers.contains_key("idempotent-replayed"), replayed);
+        let exposed = headers[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        for name in [
+            "location",
+            "retry-after",
+            "idempotent-replayed",
+            "x-request-id",
+        ] {
+            assert!(exposed.contains(name), "{} not in {}", name, exposed);
+        }
+        ids.push(headers[header::LOCATION].clone());
+    }
+    assert_eq!(ids[0], ids[1]);
+}
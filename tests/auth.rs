@@ -0,0 +1,85 @@
+// The API key guard on the managing routes.
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+
+use common::{app_with, call, coffee, config, send};
+
+fn create(key: Option<&str>) -> Request<Body> {
+    let builder = Request::builder()
+        .method(Method::POST)
+        .uri("/v1/coffee/create")
+        .header("content-type", "application/json");
+    let builder = match key {
+        Some(key) => builder.header("x-api-key", key),
+        None => builder,
+    };
+    builder
+        .body(Body::from(coffee("Lavazza").to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn missing_or_wrong_key_is_a_401() {
+    let app = app_with(config());
+    assert_eq!(
+        call(&app, create(None)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    let wrong = call(&app, create(Some("guess"))).await;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        call(&app, create(Some(common::API_KEY))).await.status(),
+        StatusCode::CREATED
+    );
+}
+
+#[tokio::test]
+async fn no_configured_key_closes_the_guarded_routes() {
+    let mut config = config();
+    config.api_key = None;
+    let app = app_with(config);
+    let (status, _, problem) = send(&app, Method::POST, "/v1/admin/compact", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(problem["code"], "UNAUTHORIZED");
+    assert_eq!(
+        call(&app, create(None)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    // reads stay open
+    let (status, _, _) = send(&app, Method::GET, "/v1/coffee/list", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn auth_disabled_opens_them() {
+    let mut config = config();
+    config.api_key = None;
+    config.auth_disabled = true;
+    let app = app_with(config);
+    assert_eq!(call(&app, create(None)).await.status(), StatusCode::CREATED);
+}
+
+// A browser on another origin asks before sending the key; the preflight must allow it.
+#[tokio::test]
+async fn preflight_allows_the_key() {
+    let preflight = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/v1/coffee/create")
+        .header(header::ORIGIN, "http://frontend.example")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-api-key")
+        .body(Body::empty())
+        .unwrap();
+    let response = call(&app_with(config()), preflight).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let allowed = response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+        .to_str()
+        .unwrap();
+    assert!(
+        allowed.split(',').any(|name| name.trim() == "x-api-key"),
+        "{}",
+        allowed
+    );
+}
@@ -0,0 +1,135 @@
+// Cart checkouts, with every type in one file and with a file per type.
+mod common;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+
+use common::{app, coffee, create_coffee, per_kind_app, send};
+
+async fn create_soda(app: &Router, stock: u32) -> String {
+    let mut body = coffee("Coke");
+    body["stock"] = json!(stock);
+    body["price_cents"] = json!(150);
+    body["carbonated"] = json!(true);
+    let (status, _, created) = send(app, Method::POST, "/v1/soda/create", Some(&body)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+    created["id"].as_str().unwrap().to_owned()
+}
+
+// A cart with two coffees and `sodas` sodas.
+async fn mixed_cart(app: &Router, coffee_id: &str, soda_id: &str, sodas: u32) -> String {
+    let (_, _, cart) = send(app, Method::POST, "/v1/cart", None).await;
+    let id = cart["id"].as_str().unwrap().to_owned();
+    let items = format!("/v1/cart/{}/items", id);
+    for (kind, drink, quantity) in [("coffee", coffee_id, 2), ("soda", soda_id, sodas)] {
+        let item = json!({"kind": kind, "id": drink, "quantity": quantity});
+        let (status, _, _) = send(app, Method::POST, &items, Some(&item)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    id
+}
+
+async fn checkout(app: &Router, cart: &str) -> (StatusCode, Value) {
+    let uri = format!("/v1/cart/{}/checkout", cart);
+    let payment = json!({"amount_cents": 1000});
+    let (status, _, body) = send(app, Method::POST, &uri, Some(&payment)).await;
+    (status, body)
+}
+
+async fn stock(app: &Router, kind: &str, id: &str) -> Value {
+    let (_, _, body) = send(app, Method::GET, &format!("/v1/{}/{}", kind, id), None).await;
+    body[kind]["stock"].clone()
+}
+
+async fn mixed_checkout(app: Router) {
+    let coffee_id = create_coffee(&app, &coffee("Lavazza")).await;
+    let soda_id = create_soda(&app, 5).await;
+    let cart = mixed_cart(&app, &coffee_id, &soda_id, 1).await;
+
+    let (status, receipt) = checkout(&app, &cart).await;
+    assert_eq!(status, StatusCode::OK, "{}", receipt);
+    assert_eq!(receipt["sale_ids"].as_array().unwrap().len(), 3);
+    assert_eq!(receipt["total_cents"], 650);
+    assert_eq!(stock(&app, "coffee", &coffee_id).await, 3);
+    assert_eq!(stock(&app, "soda", &soda_id).await, 4);
+
+    let (status, _) = checkout(&app, &cart).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn mixed_cart_checks_out_in_one_file() {
+    mixed_checkout(app()).await;
+}
+
+#[tokio::test]
+async fn mixed_cart_checks_out_across_files() {
+    mixed_checkout(per_kind_app()).await;
+}
+
+#[tokio::test]
+async fn failed_line_leaves_every_file_untouched() {
+    let app = per_kind_app();
+    let coffee_id = create_coffee(&app, &coffee("Lavazza")).await;
+    let soda_id = create_soda(&app, 1).await;
+    let cart = mixed_cart(&app, &coffee_id, &soda_id, 2).await;
+
+    let (status, problem) = checkout(&app, &cart).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(problem["code"], "OUT_OF_STOCK");
+    assert_eq!(stock(&app, "coffee", &coffee_id).await, 5);
+    assert_eq!(stock(&app, "soda", &soda_id).await, 1);
+    let (_, _, kept) = send(&app, Method::GET, &format!("/v1/cart/{}", cart), None).await;
+    assert_eq!(kept["items"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_checkouts_buy_once() {
+    let app = per_kind_app();
+    let coffee_id = create_coffee(&app, &coffee("Lavazza")).await;
+    let soda_id = create_soda(&app, 5).await;
+    let cart = mixed_cart(&app, &coffee_id, &soda_id, 1).await;
+
+    let checkouts: Vec<_> = (0..8)
+        .map(|_| {
+            let (app, cart) = (app.clone(), cart.clone());
+            tokio::spawn(async move { checkout(&app, &cart).await.0 })
+        })
+        .collect();
+    let mut bought = 0;
+    for checkout in checkouts {
+        match checkout.await.unwrap() {
+            StatusCode::OK => bought += 1,
+            status => assert_eq!(status, StatusCode::BAD_REQUEST),
+        }
+    }
+    assert_eq!(bought, 1);
+    assert_eq!(stock(&app, "coffee", &coffee_id).await, 3);
+    assert_eq!(stock(&app, "soda", &soda_id).await, 4);
+}
+
+// Each unit is a sale of its own, so a line is capped rather than left to reach u32::MAX.
+#[tokio::test]
+async fn line_over_the_cap_is_a_422() {
+    let app = app();
+    let coffee_id = create_coffee(&app, &coffee("Lavazza")).await;
+    let (_, _, cart) = send(&app, Method::POST, "/v1/cart", None).await;
+    let items = format!("/v1/cart/{}/items", cart["id"].as_str().unwrap());
+    let item = |quantity: u32| json!({"kind": "coffee", "id": coffee_id, "quantity": quantity});
+
+    let (status, _, problem) = send(&app, Method::POST, &items, Some(&item(u32::MAX))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem["code"], "VALIDATION");
+    let (status, _, _) = send(&app, Method::POST, &items, Some(&item(60))).await;
+    assert_eq!(status, StatusCode::OK);
+    // the quantities added up count too, and the refused one isn't kept
+    let (status, _, problem) = send(&app, Method::POST, &items, Some(&item(41))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        problem["errors"],
+        json!(["quantity must be at most 100 per drink, the cart would hold 101"])
+    );
+    let (_, _, cart) = send(&app, Method::POST, &items, Some(&item(40))).await;
+    assert_eq!(cart["items"][0]["quantity"], 100);
+}
@@ -0,0 +1,68 @@
+// The one-shot subcommands, run as the binary against a store in a file.
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use common::{coffee, config, create_coffee};
+use serde_json::{json, Value};
+use vending_structsy::{build_router, db::Databases, AppStateT};
+
+// A fresh directory under the temp dir, with the database path in it.
+fn dir(name: &str) -> (PathBuf, String) {
+    let dir = std::env::temp_dir().join(format!("vending-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let db_path = dir.join("track.db").display().to_string();
+    (dir, db_path)
+}
+
+// Run from `dir`, so nothing is written where the tests were started.
+fn vending(dir: &Path, db_path: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vending_structsy"))
+        .current_dir(dir)
+        .args(["--db-path", db_path])
+        .args(args)
+        .output()
+        .expect("the binary runs")
+}
+
+#[tokio::test]
+async fn export_imports_back_with_replace() {
+    let (dir, db_path) = dir("replace");
+    let mut config = config();
+    config.db_path = db_path.clone();
+    let db = Databases::open(&config).expect("the store opens");
+    let app = build_router(AppStateT::new(db, config, None));
+    create_coffee(&app, &coffee("Lavazza")).await;
+    create_coffee(&app, &coffee("Illy")).await;
+    drop(app);
+
+    let export = dir.join("export.json").display().to_string();
+    let output = vending(&dir, &db_path, &["export", "--out", &export]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = vending(&dir, &db_path, &["import", &export, "--replace"]);
+    assert!(output.status.success(), "{:?}", output);
+    let imported: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(imported, json!({ "coffees": 2, "beers": 0, "sodas": 0 }));
+
+    // appended, the same records are already there
+    let output = vending(&dir, &db_path, &["import", &export]);
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Conflict"), "{}", stderr);
+
+    std::fs::write(&export, "{ not json").unwrap();
+    let output = vending(&dir, &db_path, &["import", &export, "--replace"]);
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("BadRequest"), "{}", stderr);
+    assert!(stderr.contains("is not an export"), "{}", stderr);
+
+    // neither failed import changed what the replace left
+    let output = vending(&dir, &db_path, &["export"]);
+    let stored: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stored["coffees"].as_array().unwrap().len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
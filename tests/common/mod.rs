@@ -0,0 +1,113 @@
+// What the integration tests share: an app over an in-memory store, and requests sent to it
+// with `tower::ServiceExt::oneshot`, no socket involved.
+#![allow(dead_code)]
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use vending_structsy::config::{Config, DbFiles};
+use vending_structsy::{build_router, db::Databases, open_db, AppState};
+use vending_structsy::{AppStateT, MEMORY_DB};
+
+pub const API_KEY: &str = "test-key";
+
+// The defaults, with the tests' API key and no rate limit: oneshot requests carry no peer
+// address to key clients by.
+pub fn config() -> Config {
+    let mut config = Config::from_env().expect("the default configuration is valid");
+    config.db_path = MEMORY_DB.to_owned();
+    config.api_key = Some(API_KEY.to_owned());
+    config.rate_limit = 0.0;
+    config
+}
+
+pub fn state_with(config: Config) -> AppState {
+    let connection = open_db(MEMORY_DB).expect("an in-memory store opens");
+    AppStateT::new(Databases::single(MEMORY_DB, connection), config, None)
+}
+
+// With DB_FILES=per-kind: four in-memory stores, one for DB_PATH and one per drink type.
+pub fn per_kind_app() -> Router {
+    let mut config = config();
+    config.db_files = DbFiles::PerKind;
+    let db = Databases::open(&config).expect("the in-memory stores open");
+    build_router(AppStateT::new(db, config, None))
+}
+
+pub fn app_with(config: Config) -> Router {
+    build_router(state_with(config))
+}
+
+pub fn app() -> Router {
+    app_with(config())
+}
+
+pub async fn call(app: &Router, request: Request<Body>) -> Response {
+    app.clone()
+        .oneshot(request)
+        .await
+        .expect("the router is infallible")
+}
+
+// A request with the API key, and `body` as JSON when there is one.
+pub fn request(method: Method, uri: &str, body: Option<&Value>) -> Request<Body> {
+    let builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", API_KEY);
+    match body {
+        Some(body) => builder
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    }
+    .expect("the request is well formed")
+}
+
+// Status, headers and the body as JSON (null when empty).
+pub async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<&Value>,
+) -> (StatusCode, HeaderMap, Value) {
+    let response = call(app, request(method, uri, body)).await;
+    let (parts, body) = response.into_parts();
+    (parts.status, parts.headers, json(body).await)
+}
+
+pub async fn json(body: Body) -> Value {
+    let bytes = to_bytes(body, usize::MAX)
+        .await
+        .expect("the body is readable");
+    if bytes.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(&bytes).expect("the body is JSON")
+}
+
+pub fn coffee(brand: &str) -> Value {
+    serde_json::json!({
+        "brand": brand,
+        "machine_id": "m-1",
+        "size": 200,
+        "stock": 5,
+        "price_cents": 250,
+    })
+}
+
+// Create `body` as a coffee and return its id.
+pub async fn create_coffee(app: &Router, body: &Value) -> String {
+    let (status, _, created) = send(app, Method::POST, "/v1/coffee/create", Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+    created["id"]
+        .as_str()
+        .expect("the id is a string")
+        .to_owned()
+}
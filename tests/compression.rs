@@ -0,0 +1,49 @@
+// Responses over `MIN_COMPRESS_BYTES` are gzipped for clients that accept it.
+mod common;
+
+use std::io::Read;
+
+use axum::{
+    body::to_bytes,
+    http::{header, Method, StatusCode},
+};
+use flate2::read::GzDecoder;
+
+use common::{app, call, coffee, create_coffee, json, request};
+
+#[tokio::test]
+async fn large_list_is_gzipped() {
+    let app = app();
+    for n in 0..20 {
+        create_coffee(&app, &coffee(&format!("Brand {}", n))).await;
+    }
+
+    let mut gzipped = request(Method::GET, "/v1/coffee/list", None);
+    gzipped
+        .headers_mut()
+        .insert(header::ACCEPT_ENCODING, "gzip".parse().unwrap());
+    let response = call(&app, gzipped).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let mut text = String::new();
+    GzDecoder::new(&bytes[..])
+        .read_to_string(&mut text)
+        .unwrap();
+    let list: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(list["total"], 20);
+
+    // without `Accept-Encoding` the same list goes out as is
+    let response = call(&app, request(Method::GET, "/v1/coffee/list", None)).await;
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(json(response.into_body()).await, list);
+
+    // and so does a small body, whatever the client accepts
+    let mut small = request(Method::GET, "/v1/coffee/nope", None);
+    small
+        .headers_mut()
+        .insert(header::ACCEPT_ENCODING, "gzip".parse().unwrap());
+    let response = call(&app, small).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+}
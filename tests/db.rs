@@ -0,0 +1,36 @@
+mod common;
+
+use std::path::Path;
+
+use common::coffee;
+use structsy::StructsyTx;
+use vending_structsy::beverage::{Beverage, Coffee};
+use vending_structsy::{open_db, MEMORY_DB};
+
+#[test]
+fn memory_db_round_trips_a_coffee() {
+    let db = open_db(MEMORY_DB).unwrap();
+    let record: Coffee = serde_json::from_value(coffee("Lavazza")).unwrap();
+    let mut tx = db.begin().unwrap();
+    let id = tx.insert(&record).unwrap();
+    tx.commit().unwrap();
+
+    let read = db.read(&id).unwrap().unwrap();
+    assert_eq!(read.brand(), "Lavazza");
+    assert_eq!(read.size(), record.size());
+    assert_eq!(
+        serde_json::to_value(&read).unwrap(),
+        serde_json::to_value(&record).unwrap()
+    );
+
+    // Nothing touches the disk, and every store starts empty.
+    assert!(!Path::new(MEMORY_DB).exists());
+    assert_eq!(
+        open_db(MEMORY_DB)
+            .unwrap()
+            .scan::<Coffee>()
+            .unwrap()
+            .count(),
+        0
+    );
+}
@@ -0,0 +1,86 @@
+// The `code` of every `AppError` variant.  The codes are part of the API, so a change here
+// is a breaking change for clients.
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::{FromRequest, Query},
+    http::{header, Request},
+    response::IntoResponse,
+    Json,
+};
+use serde_json::Value;
+use structsy::StructsyError;
+
+use common::json;
+use vending_structsy::AppError;
+
+async fn json_rejection() -> AppError {
+    let request = Request::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from("{"))
+        .unwrap();
+    AppError::JsonRejection(Json::<Value>::from_request(request, &()).await.unwrap_err())
+}
+
+fn query_rejection() -> AppError {
+    let uri = "/?limit=many".parse().unwrap();
+    AppError::QueryRejection(Query::<HashMap<String, u32>>::try_from_uri(&uri).unwrap_err())
+}
+
+async fn join_error() -> AppError {
+    let task = tokio::spawn(std::future::pending::<()>());
+    task.abort();
+    AppError::JoinError(task.await.unwrap_err())
+}
+
+fn text() -> String {
+    "text".to_owned()
+}
+
+#[tokio::test]
+async fn every_variant_has_its_code() {
+    let errors = vec![
+        (json_rejection().await, "INVALID_BODY"),
+        (query_rejection(), "INVALID_QUERY"),
+        (
+            AppError::StructsyError(StructsyError::InvalidId),
+            "INTERNAL",
+        ),
+        (AppError::IOError(std::io::Error::other("disk")), "INTERNAL"),
+        (join_error().await, "INTERNAL"),
+        (AppError::Panic(text()), "INTERNAL"),
+        (AppError::Timeout(Duration::from_secs(1)), "TIMEOUT"),
+        (AppError::NotFound(text()), "NOT_FOUND"),
+        (AppError::MethodNotAllowed, "METHOD_NOT_ALLOWED"),
+        (AppError::BadRef(text()), "INVALID_ID"),
+        (AppError::Conflict(text()), "CONFLICT"),
+        (AppError::OutOfStock(text()), "OUT_OF_STOCK"),
+        (AppError::PaymentRequired(text()), "PAYMENT_REQUIRED"),
+        (AppError::Validation(vec![text()]), "VALIDATION"),
+        (
+            AppError::RateLimited(Duration::from_secs(1)),
+            "RATE_LIMITED",
+        ),
+        (AppError::Unauthorized(text()), "UNAUTHORIZED"),
+        (AppError::BadRequest(text()), "BAD_REQUEST"),
+        (AppError::UnsupportedMediaType, "UNSUPPORTED_MEDIA_TYPE"),
+        (AppError::Maintenance(1), "MAINTENANCE"),
+        (AppError::NotAcceptable(text()), "NOT_ACCEPTABLE"),
+        (AppError::Overloaded(1), "OVERLOADED"),
+    ];
+
+    let mut seen = HashSet::new();
+    for (error, code) in errors {
+        assert_eq!(error.code(), code, "{}", error);
+        assert!(AppError::CODES.contains(&code), "{} is not in CODES", code);
+        seen.insert(code);
+        let problem = json(error.into_response().into_body()).await;
+        assert_eq!(problem["code"], code);
+    }
+    // and CODES lists nothing no variant uses
+    assert_eq!(seen.len(), AppError::CODES.len());
+}
@@ -0,0 +1,51 @@
+// The `/events` stream of created drinks.
+mod common;
+
+use std::time::Duration;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request};
+use futures_util::StreamExt;
+use vending_structsy::build_router;
+
+use common::{call, coffee, config, create_coffee, state_with};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+fn subscribe() -> Request<Body> {
+    Request::get("/v1/events").body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn created_drinks_are_streamed() {
+    let app = build_router(state_with(config()));
+    let response = call(&app, subscribe()).await;
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    create_coffee(&app, &coffee("Lavazza")).await;
+    let mut frames = response.into_body().into_data_stream();
+    let frame = tokio::time::timeout(WAIT, frames.next()).await.unwrap();
+    let frame = String::from_utf8(frame.unwrap().unwrap().to_vec()).unwrap();
+    assert!(frame.starts_with("event: created\n"), "{}", frame);
+    assert!(frame.contains("\"brand\":\"Lavazza\""), "{}", frame);
+}
+
+#[tokio::test]
+async fn streams_end_on_shutdown() {
+    let state = state_with(config());
+    let app = build_router(state.clone());
+    let before = call(&app, subscribe()).await;
+    state.shutdown.start();
+    tokio::time::timeout(WAIT, to_bytes(before.into_body(), usize::MAX))
+        .await
+        .expect("an open stream ends")
+        .unwrap();
+    // one opened after the signal ends at once
+    let after = call(&app, subscribe()).await;
+    tokio::time::timeout(WAIT, to_bytes(after.into_body(), usize::MAX))
+        .await
+        .expect("a late stream ends")
+        .unwrap();
+}
@@ -0,0 +1,55 @@
+// The middleware `build_router` wraps the routes in, around test-only routes that misbehave.
+mod common;
+
+use std::time::Duration;
+
+use axum::{
+    http::{Method, StatusCode},
+    routing::get,
+    Router,
+};
+use tower_http::catch_panic::CatchPanicLayer;
+
+use common::send;
+use vending_structsy::{handle_panic, timeout};
+
+async fn panics() -> &'static str {
+    panic!("boom")
+}
+
+async fn sleeps() -> &'static str {
+    tokio::time::sleep(Duration::from_secs(30)).await;
+    "too late"
+}
+
+#[tokio::test]
+async fn panic_is_a_json_500() {
+    let app = Router::new()
+        .route("/panic", get(panics))
+        .layer(CatchPanicLayer::custom(handle_panic));
+
+    let (status, headers, problem) = send(&app, Method::GET, "/panic", None).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(headers["content-type"], "application/problem+json");
+    assert_eq!(problem["code"], "INTERNAL");
+    // what panicked stays in the logs
+    assert!(!problem["detail"].as_str().unwrap().contains("boom"));
+
+    // the router still answers afterwards
+    let (status, _, _) = send(&app, Method::GET, "/panic", None).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn slow_request_times_out() {
+    let limit = Duration::from_secs(1);
+    let app = Router::new()
+        .route("/slow", get(sleeps))
+        .layer(axum::middleware::from_fn_with_state(limit, timeout));
+
+    let (status, headers, problem) = send(&app, Method::GET, "/slow", None).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(headers["content-type"], "application/problem+json");
+    assert_eq!(problem["code"], "TIMEOUT");
+    assert_eq!(problem["detail"], "request timed out after 1s");
+}
@@ -0,0 +1,43 @@
+// `/ready` pings the database; `/health` only says the process is up.
+mod common;
+
+use axum::http::{Method, StatusCode};
+use structsy::Structsy;
+
+use common::{config, send, state_with};
+use vending_structsy::{build_router, db::Databases, AppStateT, MEMORY_DB};
+
+#[tokio::test]
+async fn ready_once_the_server_says_so() {
+    let state = state_with(config());
+    let app = build_router(state.clone());
+
+    let (status, _, health) = send(&app, Method::GET, "/ready", None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(health["status"], "not ready");
+
+    state.status.set_ready(true);
+    let (status, _, health) = send(&app, Method::GET, "/ready", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health["status"], "ready");
+
+    let (status, _, health) = send(&app, Method::GET, "/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health["status"], "ok");
+}
+
+#[tokio::test]
+async fn store_without_the_schema_is_degraded() {
+    let connection = Structsy::memory().unwrap();
+    let state = AppStateT::new(Databases::single(MEMORY_DB, connection), config(), None);
+    state.status.set_ready(true);
+    let app = build_router(state);
+
+    let (status, _, health) = send(&app, Method::GET, "/ready", None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(health["status"], "degraded");
+
+    // the process itself is still up
+    let (status, _, _) = send(&app, Method::GET, "/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,46 @@
+// Every error is an RFC 7807 problem document.
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+
+use common::{app, call, coffee, request, send};
+
+#[tokio::test]
+async fn errors_are_problem_documents() {
+    let app = app();
+    for (uri, status, title) in [
+        ("/v1/coffee/nope", StatusCode::BAD_REQUEST, "Bad Request"),
+        ("/v1/nothing", StatusCode::NOT_FOUND, "Not Found"),
+    ] {
+        let (got, headers, problem) = send(&app, Method::GET, uri, None).await;
+        assert_eq!(got, status, "{}", uri);
+        assert_eq!(headers["content-type"], "application/problem+json");
+        assert_eq!(problem["type"], "about:blank");
+        assert_eq!(problem["title"], title);
+        assert_eq!(problem["status"], status.as_u16());
+        assert!(problem["detail"].is_string(), "{}", problem);
+        assert_eq!(problem["instance"], uri);
+        assert_eq!(
+            problem["request_id"],
+            headers["x-request-id"].to_str().unwrap()
+        );
+    }
+
+    // the validation problems stay in the `errors` member
+    let (status, _, problem) =
+        send(&app, Method::POST, "/v1/coffee/create", Some(&coffee(""))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem["errors"], json!(["brand must not be empty"]));
+}
+
+#[tokio::test]
+async fn client_request_id_is_reported() {
+    let mut nope = request(Method::GET, "/v1/coffee/nope", None);
+    nope.headers_mut()
+        .insert("x-request-id", "req-42".parse().unwrap());
+    let response = call(&app(), nope).await;
+    assert_eq!(response.headers()["x-request-id"], "req-42");
+    let problem = common::json(response.into_body()).await;
+    assert_eq!(problem["request_id"], "req-42");
+}
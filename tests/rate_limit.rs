@@ -0,0 +1,35 @@
+// Per-client rate limiting.
+mod common;
+
+use std::net::SocketAddr;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, Request, StatusCode};
+
+use common::{app_with, call, config};
+
+fn list_from(peer: &str) -> Request<Body> {
+    let mut request = Request::get("/v1/coffee/list").body(Body::empty()).unwrap();
+    let peer: SocketAddr = peer.parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(peer));
+    request
+}
+
+#[tokio::test]
+async fn clients_over_their_burst_get_a_429() {
+    let mut config = config();
+    config.rate_limit = 0.001;
+    config.rate_limit_burst = 2;
+    let app = app_with(config);
+    for _ in 0..2 {
+        let response = call(&app, list_from("10.0.0.1:1000")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let limited = call(&app, list_from("10.0.0.1:1001")).await;
+    assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(limited.headers().contains_key(header::RETRY_AFTER));
+    // another client has a bucket of its own
+    let other = call(&app, list_from("10.0.0.2:1000")).await;
+    assert_eq!(other.status(), StatusCode::OK);
+}
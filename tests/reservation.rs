@@ -0,0 +1,48 @@
+// Reserved units given back to the stock by the expiry task.
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+
+use common::{coffee, config, create_coffee, send, state_with};
+use vending_structsy::{build_router, reservation};
+
+async fn fetch(app: &Router, id: &str) -> Value {
+    let (_, _, body) = send(app, Method::GET, &format!("/v1/coffee/{}", id), None).await;
+    body["coffee"].clone()
+}
+
+// A restock can fill the stock to the top while a unit is reserved; expiring it then must
+// not fail, or the reservations after it would never be released either.
+#[tokio::test]
+async fn expiry_at_full_stock_keeps_the_stock() {
+    let mut config = config();
+    config.reservation_ttl = Duration::ZERO;
+    let state = state_with(config);
+    let app = build_router(state.clone());
+    let mut body = coffee("Lavazza");
+    body["stock"] = json!(u32::MAX);
+    let id = create_coffee(&app, &body).await;
+
+    let uri = format!("/v1/coffee/{}/reserve", id);
+    let (status, _, _) = send(&app, Method::POST, &uri, None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let uri = format!("/v1/coffee/{}/restock", id);
+    let (status, _, _) = send(&app, Method::POST, &uri, Some(&json!({ "quantity": 1 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetch(&app, &id).await["version"], 3);
+
+    tokio::spawn(reservation::release_expired(state));
+    for _ in 0..50 {
+        let value = fetch(&app, &id).await;
+        if value["version"] == 4 {
+            assert_eq!(value["stock"], u32::MAX);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("the expired reservation was not released");
+}